@@ -1,24 +1,32 @@
 use crate::device::Device;
+use crate::Error;
 use ecp::{ContentData, Get};
 
 #[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct App {
     pub id: i32,
     pub apptype: String,
     pub version: String,
     pub name: String,
+    #[cfg_attr(feature = "serde", serde(default))]
     pub icon: Option<Vec<u8>>,
 }
 
 impl App {
     // Download the icon for this app from the device, then update this instance of App
     pub async fn fetch_icon(&mut self, parent_device: &mut Device) {
-        if let Some(connection) = &mut parent_device.connection {
-            if let Some(response) = connection.send_request(Get::QueryAppIcon { channel_id: self.id }.into()).await {
-                if let Some(ContentData::Data { bytes: data }) = response.content_data {
-                    self.icon = Some(data);
-                }
-            }
+        if let Ok(Some(data)) = self.query_icon(parent_device).await {
+            self.icon = Some(data);
+        }
+    }
+
+    /// Download the icon for this app, None if the device answered without one
+    pub async fn query_icon(&self, parent_device: &mut Device) -> Result<Option<Vec<u8>>, Error> {
+        let response = parent_device.request(Get::QueryAppIcon { channel_id: self.id }.into()).await?;
+        match response.content_data {
+            Some(ContentData::Data { bytes }) => Ok(Some(bytes)),
+            _ => Ok(None),
         }
     }
 }
\ No newline at end of file
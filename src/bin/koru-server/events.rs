@@ -0,0 +1,111 @@
+//! Live event feed: device changes are broadcast as JSON to every connected WebSocket
+
+use crate::entry_json;
+use futures_util::{SinkExt, StreamExt};
+use koru::{DeviceEvent, DeviceUpdate, Registry, RegistryEntry};
+use serde_json::{json, Value};
+use std::sync::Arc;
+use tokio::sync::broadcast;
+use warp::ws::{Message, WebSocket};
+
+// How many events a slow client can fall behind before it starts missing them
+const FEED_BUFFER: usize = 256;
+
+/// Fans events out to every connected client
+#[derive(Clone)]
+pub struct Feed {
+    sender: broadcast::Sender<Value>,
+}
+
+impl Feed {
+    pub fn new() -> Feed {
+        let (sender, _) = broadcast::channel(FEED_BUFFER);
+        Feed { sender }
+    }
+
+    /// Send an event to whoever is listening (nobody is fine too)
+    pub fn publish(&self, event: Value) {
+        let _ = self.sender.send(event);
+    }
+}
+
+impl Feed {
+    /// Announce a newly registered device
+    pub fn device_discovered(&self, entry: &RegistryEntry) {
+        self.publish(json!({ "event": "device-discovered", "device": entry_json(entry) }));
+    }
+
+    /// Announce a device that left the registry
+    pub fn device_lost(&self, id: &str) {
+        self.publish(json!({ "event": "device-lost", "id": id }));
+    }
+}
+
+/// Forward the changes found in each round of device updates to the feed
+pub async fn forward(mut updates: broadcast::Receiver<Arc<Vec<DeviceUpdate>>>, feed: Feed) {
+    loop {
+        match updates.recv().await {
+            Ok(round) => {
+                for update in round.iter() {
+                    for event in &update.events {
+                        feed.publish(event_json(&update.entry.id, event));
+                    }
+                }
+            }
+            Err(broadcast::error::RecvError::Lagged(missed)) => feed.publish(json!({ "event": "lagged", "missed": missed })),
+            Err(broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+/// Serve one WebSocket client: a snapshot of the known devices, then every event as it happens
+pub async fn serve(socket: WebSocket, registry: Registry, feed: Feed) {
+    // Subscribe before the snapshot so nothing falls between the two
+    let mut events = feed.sender.subscribe();
+    let (mut outgoing, mut incoming) = socket.split();
+
+    let devices: Vec<Value> = registry.list().await.iter().map(entry_json).collect();
+    let snapshot = json!({ "event": "devices", "devices": devices });
+    if outgoing.send(Message::text(snapshot.to_string())).await.is_err() {
+        return;
+    }
+
+    loop {
+        tokio::select! {
+            event = events.recv() => {
+                let event = match event {
+                    Ok(event) => event,
+                    Err(broadcast::error::RecvError::Lagged(missed)) => json!({ "event": "lagged", "missed": missed }),
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if outgoing.send(Message::text(event.to_string())).await.is_err() {
+                    break;
+                }
+            }
+            // Clients only listen; anything they send is ignored until they close
+            message = incoming.next() => {
+                match message {
+                    Some(Ok(message)) if !message.is_close() => (),
+                    _ => break,
+                }
+            }
+        }
+    }
+    let _ = outgoing.close().await;
+}
+
+/// JSON form of a device event
+fn event_json(id: &str, event: &DeviceEvent) -> Value {
+    let app = |app: &Option<koru::App>| app.as_ref().map(|a| json!({ "id": a.id, "name": a.name }));
+    let (from, to) = match event {
+        DeviceEvent::PowerChanged { from, to } => (json!(from.to_string()), json!(to.to_string())),
+        DeviceEvent::ActiveAppChanged { from, to } => (json!(app(from)), json!(app(to))),
+        DeviceEvent::MediaStateChanged { from, to } => (json!(from.to_string()), json!(to.to_string())),
+        DeviceEvent::InputChanged { from, to } => (json!(from), json!(to)),
+        DeviceEvent::NetworkChanged { from, to } => (
+            json!(from.as_ref().map(|n| n.to_string())),
+            json!(to.as_ref().map(|n| n.to_string())),
+        ),
+    };
+    json!({ "event": event.kind().to_string(), "id": id, "from": from, "to": to })
+}
@@ -0,0 +1,160 @@
+use clap::Parser;
+use events::Feed;
+use koru::{AnyFileKey, BridgeOptions, InlineKey, KeyProvider, Metrics, MqttBridge, Registry, RegistryEntry};
+use std::path::PathBuf;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+mod events;
+mod routes;
+
+/// HTTP gateway exposing discovered devices to other services
+#[derive(Parser)]
+#[command(name = "koru-server", version)]
+pub struct Args {
+    /// Address to listen on
+    #[arg(short, long, default_value = "127.0.0.1:8060")]
+    listen: SocketAddr,
+
+    /// ECP-2 key used to connect to discovered devices
+    #[arg(short, long, env = "KORU_ECP2_KEY", hide_env_values = true)]
+    key: Option<String>,
+
+    /// File holding the ECP-2 key instead, plain (readable only by the server's user) or encrypted
+    #[arg(long, env = "KORU_ECP2_KEY_FILE", conflicts_with = "key")]
+    key_file: Option<PathBuf>,
+
+    /// Passphrase for an encrypted key file
+    #[arg(long, env = "KORU_KEY_PASSPHRASE", hide_env_values = true)]
+    key_passphrase: Option<String>,
+
+    /// Seconds between discovery runs
+    #[arg(long, default_value_t = 60)]
+    discover_interval: u64,
+
+    /// Seconds each discovery run listens for devices
+    #[arg(long, default_value_t = 3)]
+    discover_timeout: u64,
+
+    /// Seconds between state checks on each device, for the event feed and metrics
+    #[arg(long, default_value_t = 2)]
+    watch_interval: u64,
+
+    /// MQTT broker to bridge devices to, e.g. localhost:1883
+    #[arg(long)]
+    mqtt: Option<String>,
+
+    /// MQTT username and password, as user:password
+    #[arg(long, env = "KORU_MQTT_CREDENTIALS", hide_env_values = true)]
+    mqtt_credentials: Option<String>,
+
+    /// Root of the bridge's MQTT topics
+    #[arg(long, default_value = "koru")]
+    mqtt_prefix: String,
+
+    /// Home Assistant discovery prefix, or "none" to skip discovery
+    #[arg(long, default_value = "homeassistant")]
+    mqtt_discovery_prefix: String,
+}
+
+#[tokio::main]
+async fn main() {
+    let args = Args::parse();
+    let key: Option<Box<dyn KeyProvider>> = match &args.key_file {
+        Some(path) => Some(Box::new(AnyFileKey::new(path, args.key_passphrase.as_deref()))),
+        None => args.key.as_ref().map(|k| Box::new(InlineKey::new(k.as_str())) as Box<dyn KeyProvider>),
+    };
+    // The key is read again for each new device, but a bad one should stop the server right away
+    if let Some(Err(e)) = key.as_ref().map(|provider| provider.key()) {
+        println!("[!] {}", e);
+        std::process::exit(1);
+    }
+    let registry = Registry::new(key);
+
+    let feed = Feed::new();
+    // One round of checks on every device feeds the event feed, the MQTT bridge and metrics alike
+    let watcher = registry.watch(Duration::from_secs(args.watch_interval));
+    tokio::spawn(events::forward(watcher.subscribe(), feed.clone()));
+
+    // Keep the registry up to date in the background
+    let discovery = registry.clone();
+    let announced = feed.clone();
+    let interval = Duration::from_secs(args.discover_interval);
+    let timeout = Duration::from_secs(args.discover_timeout);
+    tokio::spawn(async move {
+        loop {
+            match discovery.refresh(timeout).await {
+                Ok(changes) => {
+                    for id in changes.added {
+                        println!("[+] Discovered {}", id);
+                        if let Some(entry) = discovery.get(&id).await {
+                            announced.device_discovered(&entry);
+                        }
+                    }
+                    for id in changes.removed {
+                        println!("[-] Lost {}", id);
+                        announced.device_lost(&id);
+                    }
+                    for id in changes.moved {
+                        if let Some(entry) = discovery.get(&id).await {
+                            println!("[~] {} moved to {}:{}", id, entry.ipv4, entry.port);
+                        }
+                    }
+                    for (id, e) in changes.failed {
+                        println!("[!] Couldn't connect to {}: {}", id, e);
+                    }
+                }
+                Err(e) => println!("[!] {}", e),
+            }
+            tokio::time::sleep(interval).await;
+        }
+    });
+
+    // Keep device gauges fresh so scrapes don't wait on devices
+    let observed = watcher.clone();
+    tokio::spawn(async move { Metrics::global().observe_watcher(&observed).await });
+
+    if let Some(broker) = &args.mqtt {
+        let options = bridge_options(&args, broker);
+        let bridge = MqttBridge::new(watcher.clone(), options);
+        println!("[-] Bridging to MQTT broker {}", broker);
+        tokio::spawn(async move {
+            if let Err(e) = bridge.run().await {
+                println!("[!] {}", e);
+            }
+        });
+    }
+
+    println!("[-] Listening on http://{}", args.listen);
+    warp::serve(routes::routes(registry, feed)).run(args.listen).await;
+}
+
+/// MQTT bridge settings from the arguments, with the port defaulting to 1883
+fn bridge_options(args: &Args, broker: &str) -> BridgeOptions {
+    let (host, port) = match broker.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().unwrap_or(1883)),
+        None => (broker, 1883),
+    };
+    BridgeOptions {
+        host: String::from(host),
+        port,
+        credentials: args.mqtt_credentials.as_ref()
+            .map(|c| c.split_once(':').map(|(u, p)| (String::from(u), String::from(p))).unwrap_or((c.clone(), String::new()))),
+        prefix: args.mqtt_prefix.clone(),
+        discovery_prefix: Some(args.mqtt_discovery_prefix.clone()).filter(|p| !p.eq_ignore_ascii_case("none")),
+        ..Default::default()
+    }
+}
+
+/// JSON summary of a registered device
+pub fn entry_json(entry: &RegistryEntry) -> serde_json::Value {
+    serde_json::json!({
+        "id": entry.id,
+        "ip": entry.ipv4,
+        "port": entry.port,
+        "name": entry.info.friendly_name,
+        "serial": entry.info.serial_number,
+        "model": entry.info.model_name,
+        "power": entry.info.power_mode.to_string(),
+    })
+}
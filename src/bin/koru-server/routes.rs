@@ -0,0 +1,265 @@
+use crate::entry_json;
+use crate::events::{self, Feed};
+use koru::{App, Button, Error, Metrics, Registry, RegistryEntry, SearchQuery, SearchType};
+use serde_json::json;
+use std::collections::HashMap;
+use std::convert::Infallible;
+use warp::http::StatusCode;
+use warp::reply::{self, Response};
+use warp::{Filter, Rejection, Reply};
+
+/// Every gateway route
+pub fn routes(registry: Registry, feed: Feed) -> impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    let registry = warp::any().map(move || registry.clone());
+    let feed = warp::any().map(move || feed.clone());
+    let device = warp::path("devices").and(registry.clone()).and(warp::path::param::<String>());
+
+    let list = warp::path!("devices")
+        .and(warp::get())
+        .and(registry.clone())
+        .and_then(list);
+
+    let events = warp::path!("events")
+        .and(warp::ws())
+        .and(registry)
+        .and(feed)
+        .map(|ws: warp::ws::Ws, registry: Registry, feed: Feed| {
+            ws.on_upgrade(move |socket| events::serve(socket, registry, feed)).into_response()
+        });
+
+    let metrics = warp::path!("metrics")
+        .and(warp::get())
+        .map(|| reply::with_header(Metrics::global().render(), "content-type", "text/plain; version=0.0.4").into_response());
+
+    let info = device.clone()
+        .and(warp::path!("info"))
+        .and(warp::get())
+        .and_then(info);
+
+    let apps = device.clone()
+        .and(warp::path!("apps"))
+        .and(warp::get())
+        .and_then(apps);
+
+    let icon = device.clone()
+        .and(warp::path!("apps" / String / "icon"))
+        .and(warp::get())
+        .and_then(icon);
+
+    let keypress = device.clone()
+        .and(warp::path!("keypress" / String))
+        .and(warp::post())
+        .and_then(keypress);
+
+    let launch = device.clone()
+        .and(warp::path!("launch" / String))
+        .and(warp::post())
+        .and_then(launch);
+
+    let search = device.clone()
+        .and(warp::path!("search"))
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(search);
+
+    let power = device
+        .and(warp::path!("power"))
+        .and(warp::post())
+        .and(warp::query::<HashMap<String, String>>())
+        .and_then(power);
+
+    list.or(events).unify()
+        .or(metrics).unify()
+        .or(info).unify()
+        .or(apps).unify()
+        .or(icon).unify()
+        .or(keypress).unify()
+        .or(launch).unify()
+        .or(search).unify()
+        .or(power).unify()
+}
+
+/// GET /devices
+async fn list(registry: Registry) -> Result<Response, Infallible> {
+    let devices: Vec<_> = registry.list().await.iter().map(entry_json).collect();
+    Ok(reply::json(&devices).into_response())
+}
+
+/// GET /devices/{id}/info
+async fn info(registry: Registry, id: String) -> Result<Response, Infallible> {
+    let entry = match lookup(&registry, &id).await {
+        Ok(entry) => entry,
+        Err(response) => return Ok(response),
+    };
+    let info = entry.handle.device().query_device_info().await;
+    Ok(match info {
+        Ok(info) => reply::json(&info.raw).into_response(),
+        Err(e) => error_response(&e),
+    })
+}
+
+/// GET /devices/{id}/apps
+async fn apps(registry: Registry, id: String) -> Result<Response, Infallible> {
+    let entry = match lookup(&registry, &id).await {
+        Ok(entry) => entry,
+        Err(response) => return Ok(response),
+    };
+    Ok(match entry.handle.get_installed_apps().await {
+        Ok(apps) => reply::json(&apps.iter().map(app_json).collect::<Vec<_>>()).into_response(),
+        Err(e) => error_response(&e),
+    })
+}
+
+/// GET /devices/{id}/apps/{app}/icon
+async fn icon(registry: Registry, id: String, app: String) -> Result<Response, Infallible> {
+    let entry = match lookup(&registry, &id).await {
+        Ok(entry) => entry,
+        Err(response) => return Ok(response),
+    };
+    let app = match entry.handle.find_app(&decode_app(&app)).await {
+        Ok(app) => app,
+        Err(e) => return Ok(app_error_response(&e)),
+    };
+
+    Ok(match app.query_icon(&mut entry.handle.device()).await {
+        Ok(Some(bytes)) => {
+            let content_type = image_type(&bytes);
+            reply::with_header(bytes, "content-type", content_type).into_response()
+        }
+        Ok(None) => message(StatusCode::NOT_FOUND, "App has no icon"),
+        Err(e) => error_response(&e),
+    })
+}
+
+/// POST /devices/{id}/keypress/{button}
+async fn keypress(registry: Registry, id: String, button: String) -> Result<Response, Infallible> {
+    let entry = match lookup(&registry, &id).await {
+        Ok(entry) => entry,
+        Err(response) => return Ok(response),
+    };
+    let button = match urlencoding::decode(&button).map(|b| b.parse::<Button>()) {
+        Ok(Ok(button)) => button,
+        Ok(Err(e)) => return Ok(message(StatusCode::BAD_REQUEST, &e.to_string())),
+        Err(_) => return Ok(message(StatusCode::BAD_REQUEST, "Button isn't valid UTF-8")),
+    };
+    Ok(match entry.handle.press_button(button.clone()).await {
+        Ok(_) => reply::json(&json!({ "pressed": button.to_string() })).into_response(),
+        Err(e) => error_response(&e),
+    })
+}
+
+/// POST /devices/{id}/launch/{app}
+async fn launch(registry: Registry, id: String, app: String) -> Result<Response, Infallible> {
+    let entry = match lookup(&registry, &id).await {
+        Ok(entry) => entry,
+        Err(response) => return Ok(response),
+    };
+    Ok(match entry.handle.launch_app(&decode_app(&app)).await {
+        Ok(app) => reply::json(&json!({ "launched": app_json(&app) })).into_response(),
+        Err(e) => app_error_response(&e),
+    })
+}
+
+/// POST /devices/{id}/search?keyword=...&type=...&season=...&provider-id=...&launch=true
+async fn search(registry: Registry, id: String, query: HashMap<String, String>) -> Result<Response, Infallible> {
+    let entry = match lookup(&registry, &id).await {
+        Ok(entry) => entry,
+        Err(response) => return Ok(response),
+    };
+    let search = match search_query(&query) {
+        Ok(search) => search,
+        Err(e) => return Ok(message(StatusCode::BAD_REQUEST, &e)),
+    };
+    Ok(match entry.handle.search(search).await {
+        Ok(_) => reply::json(&json!({ "searched": query.get("keyword") })).into_response(),
+        Err(e) => error_response(&e),
+    })
+}
+
+/// POST /devices/{id}/power?action=on|off|toggle (default toggle)
+async fn power(registry: Registry, id: String, query: HashMap<String, String>) -> Result<Response, Infallible> {
+    let entry = match lookup(&registry, &id).await {
+        Ok(entry) => entry,
+        Err(response) => return Ok(response),
+    };
+    let handle = &entry.handle;
+    let result = match query.get("action").map(|a| a.to_ascii_lowercase()).as_deref() {
+        Some("on") => handle.power_on().await,
+        Some("off") => handle.power_off().await,
+        None | Some("toggle") => handle.toggle_power_state().await,
+        Some(other) => return Ok(message(StatusCode::BAD_REQUEST, &format!("Unknown power action \"{}\"", other))),
+    };
+    Ok(match result {
+        Ok(confirmed) => reply::json(&json!({ "power": handle.device().power_state.to_string(), "confirmed": confirmed })).into_response(),
+        Err(e) => error_response(&e),
+    })
+}
+
+/// Find a registered device, or a 404 response
+async fn lookup(registry: &Registry, id: &str) -> Result<RegistryEntry, Response> {
+    registry.get(id).await
+        .ok_or_else(|| message(StatusCode::NOT_FOUND, &format!("No device with id \"{}\"", id)))
+}
+
+/// App id or name from a URL path segment
+fn decode_app(app: &str) -> String {
+    urlencoding::decode(app).map(|a| a.into_owned()).unwrap_or_else(|_| app.to_string())
+}
+
+/// Search query from URL parameters, rejecting anything that doesn't parse
+fn search_query(query: &HashMap<String, String>) -> Result<SearchQuery, String> {
+    let mut search = SearchQuery::new(query.get("keyword").map(String::as_str).unwrap_or_default());
+    if let Some(search_type) = query.get("type") {
+        search.search_type = Some(search_type.parse::<SearchType>().map_err(|e| e.to_string())?);
+    }
+    if let Some(season) = query.get("season") {
+        search.season = Some(season.parse().map_err(|_| format!("Invalid season \"{}\"", season))?);
+    }
+    if let Some(ids) = query.get("provider-id") {
+        search.provider_ids = ids.split(',')
+            .map(|id| id.trim().parse().map_err(|_| format!("Invalid provider id \"{}\"", id)))
+            .collect::<Result<_, _>>()?;
+    }
+    search.launch = query.get("launch").is_some_and(|l| l == "true");
+    search.match_any = query.get("match-any").is_some_and(|m| m == "true");
+    Ok(search)
+}
+
+fn app_json(app: &App) -> serde_json::Value {
+    json!({ "id": app.id, "name": app.name, "type": app.apptype, "version": app.version })
+}
+
+/// Content type of an icon, from its magic bytes
+fn image_type(bytes: &[u8]) -> &'static str {
+    match bytes {
+        [0x89, b'P', b'N', b'G', ..] => "image/png",
+        [0xff, 0xd8, ..] => "image/jpeg",
+        [b'G', b'I', b'F', ..] => "image/gif",
+        _ => "application/octet-stream",
+    }
+}
+
+/// JSON error body with a status code
+fn message(status: StatusCode, message: &str) -> Response {
+    reply::with_status(reply::json(&json!({ "error": message })), status).into_response()
+}
+
+/// Error response for looking up an app, where an invalid request means it isn't installed
+fn app_error_response(e: &Error) -> Response {
+    match e {
+        Error::InvalidRequest(_) => message(StatusCode::NOT_FOUND, &e.to_string()),
+        _ => error_response(e),
+    }
+}
+
+/// Map device errors to the closest HTTP status
+fn error_response(e: &Error) -> Response {
+    let status = match e {
+        Error::Unsupported(_) => StatusCode::NOT_IMPLEMENTED,
+        Error::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+        Error::Timeout => StatusCode::GATEWAY_TIMEOUT,
+        Error::NotConnected | Error::Unreachable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        _ => StatusCode::BAD_GATEWAY,
+    };
+    message(status, &e.to_string())
+}
@@ -0,0 +1,347 @@
+use clap::{Parser, Subcommand, ValueEnum};
+use koru::{discover_devices, parse_address, AnyFileKey, App, Button, Device, DeviceInfo, EncryptedFileKey, InlineKey, KeyProvider, SearchQuery, SearchType};
+use serde_json::{json, Value};
+use std::io::{BufRead, IsTerminal, Write};
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
+
+mod remote;
+
+/// Everyday Roku device control
+#[derive(Parser)]
+#[command(name = "koru", version)]
+struct Args {
+    /// Device to use, by name, serial number or IP address (first discovered device if omitted)
+    #[arg(short, long, global = true)]
+    device: Option<String>,
+
+    /// ECP-2 key, needed for anything beyond discovery and queries
+    #[arg(short, long, global = true, env = "KORU_ECP2_KEY", hide_env_values = true)]
+    key: Option<String>,
+
+    /// File holding the ECP-2 key instead, plain (readable only by you) or encrypted
+    #[arg(long, global = true, env = "KORU_ECP2_KEY_FILE", conflicts_with = "key")]
+    key_file: Option<PathBuf>,
+
+    /// Passphrase for an encrypted key file
+    #[arg(long, global = true, env = "KORU_KEY_PASSPHRASE", hide_env_values = true)]
+    key_passphrase: Option<String>,
+
+    /// Seconds to spend discovering devices
+    #[arg(long, global = true, default_value_t = 3)]
+    discover_timeout: u64,
+
+    /// Print JSON instead of text
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// List devices on the network
+    Discover,
+    /// Show device info
+    Info,
+    /// List installed apps
+    Apps,
+    /// Launch an app by name or id
+    Launch { app: String },
+    /// Press one or more buttons (e.g. Home Down Down Select)
+    Press {
+        #[arg(required = true)]
+        buttons: Vec<String>,
+    },
+    /// Type text into an open on-screen keyboard
+    Type { text: String },
+    /// Open the search UI with a structured query
+    Search {
+        keyword: String,
+        /// movie, tv-show, person, channel or game
+        #[arg(short = 't', long = "type")]
+        search_type: Option<SearchType>,
+        /// Season to select for TV shows
+        #[arg(long)]
+        season: Option<u32>,
+        /// Channel ids to prefer, in priority order
+        #[arg(long = "provider")]
+        provider_ids: Vec<i32>,
+        /// Launch the first matching provider
+        #[arg(long)]
+        launch: bool,
+    },
+    /// Turn the screen on or off
+    Power { action: PowerAction },
+    /// Show the app in the foreground
+    Active,
+    /// Control the device interactively from the keyboard
+    Remote,
+    /// Download every app icon
+    Icons {
+        /// Directory to write <app id>.png files to
+        #[arg(short, long)]
+        out: PathBuf,
+    },
+    /// Encrypt an ECP-2 key with a passphrase into a new key file
+    ///
+    /// Both are prompted for, or read from stdin (the key on the first line, the passphrase on the second),
+    /// so neither ends up in the shell history or the process list
+    EncryptKey {
+        #[arg(short, long)]
+        out: PathBuf,
+    },
+}
+
+#[derive(Clone, ValueEnum)]
+enum PowerAction {
+    On,
+    Off,
+    Toggle,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(&args).await {
+        Ok(output) => {
+            print(&args, &output);
+            ExitCode::SUCCESS
+        }
+        Err(message) => {
+            if args.json {
+                println!("{}", json!({ "error": message }));
+            } else {
+                eprintln!("[!] {}", message);
+            }
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Run a command, returning its output as JSON (text output is derived from it)
+async fn run(args: &Args) -> Result<Value, String> {
+    if let Command::EncryptKey { out } = &args.command {
+        let key = read_secret("ECP-2 key")?;
+        let passphrase = read_secret("Passphrase")?;
+        if std::io::stdin().is_terminal() && read_secret("Repeat passphrase")? != passphrase {
+            return Err(String::from("The passphrases don't match"));
+        }
+        EncryptedFileKey::create(out, key.as_bytes(), &passphrase).map_err(|e| e.to_string())?;
+        return Ok(json!({ "written": out.display().to_string() }));
+    }
+    if let Command::Discover = args.command {
+        let mut found = Vec::new();
+        for device in discover(args).await? {
+            let info = device.query_device_info().await.unwrap_or_default();
+            found.push(device_json(&device, &info));
+        }
+        return Ok(Value::Array(found));
+    }
+
+    let mut device = select_device(args).await?;
+    match &args.command {
+        Command::Discover | Command::EncryptKey { .. } => unreachable!(),
+        Command::Info => {
+            let info = device.query_device_info().await.map_err(|e| e.to_string())?;
+            Ok(json!(info.raw))
+        }
+        Command::Apps => {
+            let apps = device.get_installed_apps().await.map_err(|e| e.to_string())?;
+            Ok(Value::Array(apps.iter().map(app_json).collect()))
+        }
+        Command::Launch { app } => {
+            let launched = device.launch_app(app).await.map_err(|e| e.to_string())?;
+            Ok(json!({ "launched": app_json(&launched) }))
+        }
+        Command::Press { buttons } => {
+            let buttons = buttons.iter()
+                .map(|b| b.parse::<Button>().map_err(|e| e.to_string()))
+                .collect::<Result<Vec<_>, _>>()?;
+            let report = device.press_buttons_with(buttons, &Default::default()).await;
+            if let Some((button, e)) = report.failed.first() {
+                return Err(format!("{} failed: {}", button, e));
+            }
+            Ok(json!({ "pressed": report.delivered.iter().map(|b| b.to_string()).collect::<Vec<_>>() }))
+        }
+        Command::Type { text } => {
+            device.type_text(text).await.map_err(|e| e.to_string())?;
+            Ok(json!({ "typed": text }))
+        }
+        Command::Search { keyword, search_type, season, provider_ids, launch } => {
+            let query = SearchQuery {
+                keyword: keyword.clone(),
+                search_type: search_type.clone(),
+                season: *season,
+                provider_ids: provider_ids.clone(),
+                launch: *launch,
+                match_any: false,
+            };
+            device.search(query).await.map_err(|e| e.to_string())?;
+            Ok(json!({ "searched": keyword }))
+        }
+        Command::Power { action } => {
+            let worked = match action {
+                PowerAction::On => device.power_on().await,
+                PowerAction::Off => device.power_off().await,
+                PowerAction::Toggle => device.toggle_power_state().await,
+            }.map_err(|e| e.to_string())?;
+            Ok(json!({ "power": device.power_state.to_string(), "confirmed": worked }))
+        }
+        Command::Remote => {
+            remote::run(device).await?;
+            Ok(json!({}))
+        }
+        Command::Active => {
+            let app = device.get_active_app().await.map_err(|e| e.to_string())?;
+            Ok(json!({ "active": app.as_ref().map(app_json) }))
+        }
+        Command::Icons { out } => {
+            std::fs::create_dir_all(out).map_err(|e| format!("Unable to create {}: {}", out.display(), e))?;
+            let mut written = Vec::new();
+            for mut app in device.get_installed_apps().await.map_err(|e| e.to_string())? {
+                app.fetch_icon(&mut device).await;
+                if let Some(icon) = &app.icon {
+                    let path = out.join(format!("{}.png", app.id));
+                    std::fs::write(&path, icon).map_err(|e| format!("Unable to write {}: {}", path.display(), e))?;
+                    written.push(path.display().to_string());
+                }
+            }
+            Ok(json!({ "written": written }))
+        }
+    }
+}
+
+/// Discover devices, failing if there aren't any
+async fn discover(args: &Args) -> Result<Vec<Device>, String> {
+    let devices = discover_devices(Duration::from_secs(args.discover_timeout)).await
+        .map_err(|e| format!("Discovery failed: {}", e))?;
+    if devices.is_empty() {
+        return Err(String::from("No devices found"));
+    }
+    Ok(devices)
+}
+
+/// Find the device the arguments refer to, and connect to it if there's a key
+async fn select_device(args: &Args) -> Result<Device, String> {
+    let mut device = match &args.device {
+        // An address doesn't need discovery, e.g. 192.168.1.20 or 192.168.1.20:8060
+        Some(selector) if parse_address(selector).is_some() => {
+            let (ipv4, port) = parse_address(selector).unwrap();
+            Device::new(&ipv4.to_string(), port)
+        }
+        Some(selector) => {
+            let mut found = None;
+            for device in discover(args).await? {
+                let info = device.query_device_info().await.unwrap_or_default();
+                let user_name = info.raw.get("user-device-name").cloned().unwrap_or_default();
+                if info.friendly_name.eq_ignore_ascii_case(selector)
+                    || user_name.eq_ignore_ascii_case(selector)
+                    || info.serial_number.eq_ignore_ascii_case(selector) {
+                    found = Some(device);
+                    break;
+                }
+            }
+            found.ok_or(format!("No device named \"{}\" found", selector))?
+        }
+        None => discover(args).await?.remove(0),
+    };
+
+    if let Some(provider) = key(args) {
+        if !device.connect(provider.key().map_err(|e| e.to_string())?).await {
+            return Err(format!("Unable to connect to {}, check the ECP-2 key", device.ipv4));
+        }
+        device.update_self().await;
+    }
+    Ok(device)
+}
+
+/// Where the ECP-2 key comes from, --key or --key-file, if either was given
+fn key(args: &Args) -> Option<Box<dyn KeyProvider>> {
+    if let Some(path) = &args.key_file {
+        return Some(Box::new(AnyFileKey::new(path, args.key_passphrase.as_deref())));
+    }
+    args.key.as_ref().map(|k| Box::new(InlineKey::new(k.as_str())) as Box<dyn KeyProvider>)
+}
+
+/// Prompt for a secret without echoing it, or read the next line of stdin when it isn't a terminal
+fn read_secret(prompt: &str) -> Result<String, String> {
+    use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+    use crossterm::terminal;
+
+    let secret = if std::io::stdin().is_terminal() {
+        eprint!("{}: ", prompt);
+        let _ = std::io::stderr().flush();
+        terminal::enable_raw_mode().map_err(|e| e.to_string())?;
+        let mut secret = String::new();
+        let read = loop {
+            match event::read() {
+                Ok(Event::Key(key)) if key.kind != KeyEventKind::Release => match key.code {
+                    KeyCode::Enter => break Ok(()),
+                    KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => break Err(String::from("Cancelled")),
+                    KeyCode::Backspace => { secret.pop(); }
+                    KeyCode::Char(c) => secret.push(c),
+                    _ => {}
+                },
+                Ok(_) => {}
+                Err(e) => break Err(e.to_string()),
+            }
+        };
+        let _ = terminal::disable_raw_mode();
+        eprintln!();
+        read.map(|_| secret)?
+    } else {
+        let mut line = String::new();
+        std::io::stdin().lock().read_line(&mut line).map_err(|e| format!("Unable to read the {}: {}", prompt.to_lowercase(), e))?;
+        String::from(line.trim_end_matches(['\r', '\n']))
+    };
+    if secret.is_empty() {
+        return Err(format!("The {} can't be empty", prompt.to_lowercase()));
+    }
+    Ok(secret)
+}
+
+fn device_json(device: &Device, info: &DeviceInfo) -> Value {
+    json!({
+        "ip": device.ipv4,
+        "port": device.port,
+        "name": info.friendly_name,
+        "serial": info.serial_number,
+        "model": info.model_name,
+        "power": info.power_mode.to_string(),
+    })
+}
+
+fn app_json(app: &App) -> Value {
+    json!({ "id": app.id, "name": app.name, "type": app.apptype, "version": app.version })
+}
+
+/// Print command output as JSON, or as plain text lines
+fn print(args: &Args, output: &Value) {
+    if args.json {
+        println!("{}", output);
+        return;
+    }
+    match output {
+        Value::Array(items) => items.iter().for_each(|item| println!("{}", text(item))),
+        Value::Object(fields) => {
+            for (key, value) in fields {
+                println!("{}: {}", key, text(value));
+            }
+        }
+        other => println!("{}", text(other)),
+    }
+}
+
+/// Flatten a JSON value into a single line of text
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::from("-"),
+        Value::Array(items) => items.iter().map(text).collect::<Vec<_>>().join(", "),
+        Value::Object(fields) => fields.values().map(text).collect::<Vec<_>>().join("  "),
+        other => other.to_string(),
+    }
+}
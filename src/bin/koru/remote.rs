@@ -0,0 +1,221 @@
+//! Interactive virtual remote: keyboard keys become button presses
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute, queue};
+use koru::{Button, Device, DeviceEvent};
+use std::io::{stdout, Write};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+// How often the status bar is checked against the device
+const STATUS_INTERVAL: Duration = Duration::from_secs(1);
+// How long the input thread waits for a key before checking whether to stop
+const INPUT_POLL: Duration = Duration::from_millis(100);
+
+// Key bindings shown on screen, in remote mode
+const HELP: &[(&str, &str)] = &[
+    ("arrows", "Up/Down/Left/Right"),
+    ("enter", "Select"),
+    ("backspace", "Back"),
+    ("h", "Home"),
+    ("space", "Play/Pause"),
+    ("< >", "Rewind/Forward"),
+    ("r", "Instant replay"),
+    ("i", "Info"),
+    ("+ - m", "Volume up/down/mute"),
+    ("p", "Power"),
+    ("t", "Text entry mode (esc to leave)"),
+    ("q", "Quit"),
+];
+
+/// What a key press should do
+#[derive(Debug, Eq, PartialEq)]
+enum Action {
+    Press(Button),
+    EnterText,
+    LeaveText,
+    Quit,
+}
+
+/// What's shown in the status bar
+struct Status {
+    name:   String,
+    power:  String,
+    app:    String,
+    mode:   &'static str,
+    last:   String,  // Last button sent, or the last error
+}
+
+/// Restores the terminal however the remote exits
+struct TerminalGuard;
+
+impl TerminalGuard {
+    fn enter() -> std::io::Result<TerminalGuard> {
+        terminal::enable_raw_mode()?;
+        execute!(stdout(), EnterAlternateScreen, cursor::Hide)?;
+        Ok(TerminalGuard)
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        let _ = execute!(stdout(), cursor::Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+/// Run the remote until the user quits
+pub async fn run(mut device: Device) -> Result<(), String> {
+    let mut status = Status {
+        name: device.name.clone(),
+        power: String::from("?"),
+        app: String::from("?"),
+        mode: "remote",
+        last: String::new(),
+    };
+    refresh_status(&device, &mut status).await;
+
+    let _terminal = TerminalGuard::enter().map_err(|e| format!("Unable to set up terminal: {}", e))?;
+    let mut keys = spawn_input();
+    let mut watcher = device.watch(STATUS_INTERVAL);
+    let mut text_mode = false;
+
+    loop {
+        draw(&status, text_mode).map_err(|e| format!("Unable to draw: {}", e))?;
+        tokio::select! {
+            key = keys.recv() => {
+                let Some(key) = key else { break };
+                match action_for(key, text_mode) {
+                    Some(Action::Quit) => break,
+                    Some(Action::EnterText) => text_mode = true,
+                    Some(Action::LeaveText) => text_mode = false,
+                    Some(Action::Press(button)) => {
+                        let name = button.to_string();
+                        status.last = match device.press_button(button).await {
+                            Ok(_) => name,
+                            Err(e) => format!("{}: {}", name, e),
+                        };
+                    }
+                    None => (),
+                }
+                status.mode = if text_mode { "text" } else { "remote" };
+            }
+            event = watcher.next() => {
+                match event {
+                    Some(DeviceEvent::PowerChanged { to, .. }) => status.power = to.to_string(),
+                    Some(DeviceEvent::ActiveAppChanged { to, .. }) => status.app = app_name(to.as_ref()),
+                    Some(_) => (),
+                    None => break,
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Read the current power state and active app
+async fn refresh_status(device: &Device, status: &mut Status) {
+    if let Ok(info) = device.query_device_info().await {
+        status.power = info.power_mode.to_string();
+        if status.name.is_empty() {
+            status.name = info.friendly_name;
+        }
+    }
+    if let Ok(app) = device.get_active_app().await {
+        status.app = app_name(app.as_ref());
+    }
+}
+
+fn app_name(app: Option<&koru::App>) -> String {
+    app.map(|a| a.name.clone()).unwrap_or(String::from("Home"))
+}
+
+/// Read keys on a blocking thread and forward them to the async loop
+fn spawn_input() -> mpsc::UnboundedReceiver<KeyEvent> {
+    let (sender, keys) = mpsc::unbounded_channel();
+    std::thread::spawn(move || loop {
+        match event::poll(INPUT_POLL) {
+            Ok(true) => {
+                if let Ok(Event::Key(key)) = event::read() {
+                    if key.kind != KeyEventKind::Release && sender.send(key).is_err() {
+                        return;
+                    }
+                }
+            }
+            Ok(false) => {
+                if sender.is_closed() {
+                    return;
+                }
+            }
+            Err(_) => return,
+        }
+    });
+    keys
+}
+
+/// Map a key to what it does in the current mode
+fn action_for(key: KeyEvent, text_mode: bool) -> Option<Action> {
+    if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+        return Some(Action::Quit);
+    }
+
+    // Text entry forwards characters as literals
+    if text_mode {
+        return match key.code {
+            KeyCode::Esc => Some(Action::LeaveText),
+            KeyCode::Enter => Some(Action::Press(Button::Enter)),
+            KeyCode::Backspace => Some(Action::Press(Button::Backspace)),
+            KeyCode::Char(c) => Some(Action::Press(Button::from(c))),
+            _ => None,
+        };
+    }
+
+    let button = match key.code {
+        KeyCode::Up => Button::Up,
+        KeyCode::Down => Button::Down,
+        KeyCode::Left => Button::Left,
+        KeyCode::Right => Button::Right,
+        KeyCode::Enter => Button::Select,
+        KeyCode::Backspace | KeyCode::Esc => Button::Back,
+        KeyCode::Char('h') => Button::Home,
+        KeyCode::Char(' ') => Button::PlayPause,
+        KeyCode::Char('<') | KeyCode::Char(',') => Button::Rewind,
+        KeyCode::Char('>') | KeyCode::Char('.') => Button::Forward,
+        KeyCode::Char('r') => Button::InstantReplay,
+        KeyCode::Char('i') => Button::Info,
+        KeyCode::Char('+') | KeyCode::Char('=') => Button::VolumeUp,
+        KeyCode::Char('-') => Button::VolumeDown,
+        KeyCode::Char('m') => Button::VolumeMute,
+        KeyCode::Char('p') => Button::Power,
+        KeyCode::Char('t') => return Some(Action::EnterText),
+        KeyCode::Char('q') => return Some(Action::Quit),
+        _ => return None,
+    };
+    Some(Action::Press(button))
+}
+
+/// Redraw the key bindings and the status bar
+fn draw(status: &Status, text_mode: bool) -> std::io::Result<()> {
+    let mut out = stdout();
+    let (_, rows) = terminal::size()?;
+    queue!(out, Clear(ClearType::All), cursor::MoveTo(0, 0))?;
+
+    if text_mode {
+        queue!(out, Print("Text entry: typed characters are sent to the device, esc to leave\r\n"))?;
+    } else {
+        for (key, action) in HELP {
+            queue!(out, Print(format!("{:>10}  {}\r\n", key, action)))?;
+        }
+    }
+
+    queue!(
+        out,
+        cursor::MoveTo(0, rows.saturating_sub(1)),
+        SetAttribute(Attribute::Reverse),
+        Print(format!(" {} | power: {} | app: {} | mode: {} | {} ", status.name, status.power, status.app, status.mode, status.last)),
+        SetAttribute(Attribute::Reset),
+    )?;
+    out.flush()
+}
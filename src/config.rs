@@ -1,12 +1,252 @@
-use config::{Config, File};
+use crate::device::{parse_address, parse_mac};
+use crate::keys::REDACTED;
+use crate::{discover_devices, AnyFileKey, Device, Error, InlineKey, KeyProvider, Transport};
+use config::builder::DefaultState;
+use config::{Config, ConfigBuilder, Environment, File};
+use serde::Deserialize;
 use std::collections::HashMap;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::sync::Arc;
+use std::time::Duration;
 
-#[allow(dead_code)]
-pub fn load_from_file(path: &str) -> HashMap<String, String> {
-    let config = Config::builder()
-        .add_source(File::with_name(path))
-        .build()
-        .unwrap();
+// Environment variables override the file as KORU_<KEY>, with __ between nested keys
+// e.g. KORU_ECP2_KEY, or KORU_DEVICES__LIVING_ROOM__ADDRESS for the living_room profile
+const ENV_PREFIX: &str = "KORU";
+const ENV_SEPARATOR: &str = "__";
 
-    config.try_deserialize::<HashMap<String, String>>().unwrap()
-}
\ No newline at end of file
+/// Settings and named device profiles, from a TOML/YAML/JSON file and/or the environment
+// NOTE: Environment variable names are lowercased, so only lowercase profile names can be overridden
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct KoruConfig {
+    pub ecp2_key:           Option<String>,                     // ECP-2 key for profiles without their own
+    pub ecp2_key_file:      Option<String>,                     // File holding it instead, plain (chmod 600) or encrypted
+    pub key_passphrase:     Option<String>,                     // Passphrase for encrypted key files (set KORU_KEY_PASSPHRASE)
+    pub discovery_timeout:  f64,                                // Seconds to search for profiles that only have a serial
+    pub timeout:            Option<f64>,                        // Request timeout (seconds) for profiles without their own
+    pub devices:            HashMap<String, DeviceProfile>,     // Device profiles, by name
+    #[serde(skip)]
+    key_provider:           Option<Arc<dyn KeyProvider>>,       // Shared key from code, see with_key_provider
+}
+
+impl Default for KoruConfig {
+    fn default() -> Self {
+        KoruConfig {
+            ecp2_key: None,
+            ecp2_key_file: None,
+            key_passphrase: None,
+            discovery_timeout: 3.0,
+            timeout: None,
+            devices: HashMap::new(),
+            key_provider: None,
+        }
+    }
+}
+
+impl fmt::Debug for KoruConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KoruConfig")
+            .field("ecp2_key", &self.ecp2_key.as_ref().map(|_| REDACTED))
+            .field("ecp2_key_file", &self.ecp2_key_file)
+            .field("key_passphrase", &self.key_passphrase.as_ref().map(|_| REDACTED))
+            .field("discovery_timeout", &self.discovery_timeout)
+            .field("timeout", &self.timeout)
+            .field("devices", &self.devices)
+            .field("key_provider", &self.key_provider.as_ref().map(|_| REDACTED))
+            .finish()
+    }
+}
+
+/// How to find, wake and talk to one device
+#[derive(Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct DeviceProfile {
+    pub address:            Option<String>,     // "192.168.1.20" or "192.168.1.20:8060"
+    pub serial:             Option<String>,     // Serial number, found through discovery when there's no address
+    pub name:               Option<String>,     // Display name (defaults to the profile name)
+    pub mac_wol:            Option<String>,     // MAC used for Wake-on-LAN, e.g. "aa:bb:cc:dd:ee:ff"
+    pub mac_eth:            Option<String>,     // Ethernet MAC
+    pub mac_wlan:           Option<String>,     // WLAN MAC
+    pub transport:          Transport,          // How to send commands
+    pub key:                Option<String>,     // ECP-2 key for this device
+    pub key_file:           Option<String>,     // File holding it instead, plain (chmod 600) or encrypted
+    pub wol:                WolProfile,         // Wake-on-LAN packet settings
+    pub timeout:            Option<f64>,        // Request timeout, in seconds
+    pub keepalive:          Option<f64>,        // Seconds idle before the connection is checked
+    pub reconnect_attempts: Option<u32>,        // Reconnect attempts before giving up
+}
+
+impl fmt::Debug for DeviceProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceProfile")
+            .field("address", &self.address)
+            .field("serial", &self.serial)
+            .field("name", &self.name)
+            .field("mac_wol", &self.mac_wol)
+            .field("mac_eth", &self.mac_eth)
+            .field("mac_wlan", &self.mac_wlan)
+            .field("transport", &self.transport)
+            .field("key", &self.key.as_ref().map(|_| REDACTED))
+            .field("key_file", &self.key_file)
+            .field("wol", &self.wol)
+            .field("timeout", &self.timeout)
+            .field("keepalive", &self.keepalive)
+            .field("reconnect_attempts", &self.reconnect_attempts)
+            .finish()
+    }
+}
+
+/// Wake-on-LAN settings for a profile, anything unset keeps the default
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct WolProfile {
+    pub broadcast:          Option<Ipv4Addr>,   // e.g. a directed broadcast like 192.168.20.255
+    pub port:               Option<u16>,        // Usually 9, sometimes 7
+    pub source:             Option<Ipv4Addr>,   // Local address to send from
+    pub interface:          Option<String>,     // Network interface to send on (Linux only)
+    pub password:           Option<String>,     // SecureOn password as hex bytes, e.g. "01:02:03:04"
+}
+
+impl KoruConfig {
+    /// Load a config file (format picked from the extension), with environment overrides
+    pub fn load(path: &str) -> Result<KoruConfig, Error> {
+        KoruConfig::build(Config::builder().add_source(File::with_name(path)), Environment::default())
+    }
+
+    /// Load settings from the environment alone
+    pub fn from_env() -> Result<KoruConfig, Error> {
+        KoruConfig::build(Config::builder(), Environment::default())
+    }
+
+    pub(crate) fn build(builder: ConfigBuilder<DefaultState>, env: Environment) -> Result<KoruConfig, Error> {
+        let env = env.prefix(ENV_PREFIX).prefix_separator("_").separator(ENV_SEPARATOR);
+        builder
+            .add_source(env)
+            .build()
+            .and_then(|c| c.try_deserialize())
+            .map_err(|e| Error::Config(e.to_string()))
+    }
+
+    /// Use this provider's key for profiles without their own, instead of ecp2_key/ecp2_key_file
+    pub fn with_key_provider(mut self, provider: Box<dyn KeyProvider>) -> KoruConfig {
+        self.key_provider = Some(Arc::from(provider));
+        self
+    }
+
+    /// Look up a device profile by name, ignoring case
+    pub fn profile(&self, name: &str) -> Option<&DeviceProfile> {
+        self.named_profile(name).map(|(_, profile)| profile)
+    }
+
+    fn named_profile(&self, name: &str) -> Option<(&str, &DeviceProfile)> {
+        self.devices.get_key_value(name)
+            .or_else(|| self.devices.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)))
+            .map(|(name, profile)| (name.as_str(), profile))
+    }
+
+    /// Build a device from a profile, finding it by serial if needed and connecting if there's a key
+    pub async fn device(&self, name: &str) -> Result<Device, Error> {
+        let (name, profile) = self.named_profile(name)
+            .ok_or_else(|| Error::Config(format!("No device profile named \"{}\"", name)))?;
+
+        let mut device = match (&profile.address, &profile.serial) {
+            (Some(address), _) => {
+                let (ipv4, port) = parse_address(address)
+                    .ok_or_else(|| Error::Config(format!("Invalid address \"{}\" for {}", address, name)))?;
+                Device::new(&ipv4.to_string(), port)
+            }
+            (None, Some(serial)) => self.discover(serial).await?,
+            (None, None) => return Err(Error::Config(format!("Profile {} needs an address or a serial", name))),
+        };
+        profile.apply(name, &mut device, self.timeout)?;
+        device.transport = profile.transport;
+
+        if profile.transport == Transport::Ecp2 {
+            if let Some(provider) = self.key_for(profile) {
+                if !device.connect(provider.key()?).await {
+                    return Err(Error::NotConnected);
+                }
+            }
+        }
+        Ok(device)
+    }
+
+    /// Where the profile's ECP-2 key comes from, falling back to the shared one
+    pub(crate) fn key_for(&self, profile: &DeviceProfile) -> Option<Arc<dyn KeyProvider>> {
+        let passphrase = self.key_passphrase.as_deref();
+        if let Some(key) = &profile.key {
+            return Some(Arc::new(InlineKey::new(key.as_str())));
+        }
+        if let Some(path) = &profile.key_file {
+            return Some(Arc::new(AnyFileKey::new(path, passphrase)));
+        }
+        if let Some(provider) = &self.key_provider {
+            return Some(provider.clone());
+        }
+        if let Some(key) = &self.ecp2_key {
+            return Some(Arc::new(InlineKey::new(key.as_str())));
+        }
+        self.ecp2_key_file.as_ref().map(|path| Arc::new(AnyFileKey::new(path, passphrase)) as Arc<dyn KeyProvider>)
+    }
+
+    /// Find a device on the network by serial number
+    async fn discover(&self, serial: &str) -> Result<Device, Error> {
+        let devices = discover_devices(seconds(self.discovery_timeout, "discovery")?).await
+            .map_err(|e| Error::Discovery(e.to_string()))?;
+        for device in devices {
+            if let Ok(info) = device.query_device_info().await {
+                if info.serial_number.eq_ignore_ascii_case(serial) {
+                    return Ok(device);
+                }
+            }
+        }
+        Err(Error::Config(format!("No device with serial {} found", serial)))
+    }
+}
+
+impl DeviceProfile {
+    /// Copy this profile's settings onto a device
+    pub(crate) fn apply(&self, name: &str, device: &mut Device, default_timeout: Option<f64>) -> Result<(), Error> {
+        let mac = |mac: &Option<String>| match mac {
+            Some(mac) => parse_mac(mac).map(Some).ok_or_else(|| Error::Config(format!("Invalid MAC \"{}\" for {}", mac, name))),
+            None => Ok(None),
+        };
+        device.name = self.name.clone().unwrap_or(String::from(name));
+        device.mac_wol = mac(&self.mac_wol)?.or(device.mac_wol);
+        device.mac_eth = mac(&self.mac_eth)?.or(device.mac_eth);
+        device.mac_wlan = mac(&self.mac_wlan)?.or(device.mac_wlan);
+
+        if let Some(broadcast) = self.wol.broadcast {
+            device.wol.broadcast = broadcast;
+        }
+        if let Some(port) = self.wol.port {
+            device.wol.port = port;
+        }
+        device.wol.source = self.wol.source;
+        device.wol.interface = self.wol.interface.clone();
+        if let Some(password) = &self.wol.password {
+            let bytes = password.split([':', '-'])
+                .map(|b| u8::from_str_radix(b, 16).ok())
+                .collect::<Option<Vec<u8>>>()
+                .ok_or_else(|| Error::Config(format!("Invalid Wake-on-LAN password for {}", name)))?;
+            device.wol.password = Some(bytes);
+        }
+
+        if let Some(timeout) = self.timeout.or(default_timeout) {
+            device.timeout = seconds(timeout, name)?;
+        }
+        if let Some(keepalive) = self.keepalive {
+            device.reconnect.keepalive = seconds(keepalive, name)?;
+        }
+        if let Some(attempts) = self.reconnect_attempts {
+            device.reconnect.max_attempts = attempts;
+        }
+        Ok(())
+    }
+}
+
+/// Seconds from a config value, which could be anything
+fn seconds(value: f64, name: &str) -> Result<Duration, Error> {
+    Duration::try_from_secs_f64(value).map_err(|_| Error::Config(format!("Invalid duration {} for {}", value, name)))
+}
@@ -0,0 +1,354 @@
+use crate::{Device, Error};
+use ecp::{Connection, Get, Request, Response};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot, watch};
+use tokio::time::Instant;
+
+/// Lifecycle of a device's ECP-2 connection
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ConnectionState {
+    Disconnected,               // Never connected
+    Connecting,                 // Opening and authenticating
+    Connected,                  // Open and answering
+    Reconnecting { attempt: u32 },  // Connection went dead, re-establishing it with the stored key (attempt 0 until the first try)
+    Failed,                     // Couldn't (re)connect, requests fail until a keepalive or connect brings it back
+}
+
+/// How a device's commands are sent
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
+pub enum Transport {
+    #[default]
+    Ecp2,   // Authenticated ECP-2 connection, when there's a key
+    Http,   // Plain ECP over HTTP (port 8060), never opens an ECP-2 connection
+}
+
+/// When to check on the connection and how hard to try bringing it back
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct ReconnectOptions {
+    pub keepalive:          Duration,   // Ping the connection once it has been idle this long
+    pub initial_backoff:    Duration,   // Wait before the second reconnect attempt
+    pub max_backoff:        Duration,   // Longest wait between attempts, the backoff doubles up to this
+    pub max_attempts:       u32,        // Attempts before giving up and reporting Failed (0 disables reconnecting)
+}
+
+impl Default for ReconnectOptions {
+    fn default() -> Self {
+        ReconnectOptions {
+            keepalive: Duration::from_secs(30),
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+            max_attempts: 5,
+        }
+    }
+}
+
+/// Connection state shared between clones of a device and its connection task
+#[derive(Clone, Debug)]
+pub(crate) struct ConnectionHealth {
+    state:          Arc<watch::Sender<ConnectionState>>,    // Current state, observable via watch_connection
+}
+
+impl Default for ConnectionHealth {
+    fn default() -> Self {
+        ConnectionHealth {
+            state: Arc::new(watch::channel(ConnectionState::Disconnected).0),
+        }
+    }
+}
+
+impl ConnectionHealth {
+    /// Update the state, notifying anyone watching it
+    pub(crate) fn set(&self, state: ConnectionState) {
+        self.state.send_replace(state);
+    }
+
+    pub(crate) fn get(&self) -> ConnectionState {
+        self.state.borrow().clone()
+    }
+}
+
+/// What a connection task sends requests over: an ECP-2 connection, or a stand-in in tests
+pub(crate) trait Link: Send + 'static {
+    type Request: Clone + Send + 'static;
+    type Response: Send + 'static;
+
+    /// Send a request and wait for its response, None if the connection closed first
+    fn send(&mut self, request: Self::Request) -> impl Future<Output = Option<Self::Response>> + Send;
+
+    /// Request used to check that the connection still answers
+    fn ping() -> Self::Request;
+}
+
+impl Link for Connection {
+    type Request = Request;
+    type Response = Response;
+
+    async fn send(&mut self, request: Request) -> Option<Response> {
+        let request_id = self.next_sync_number();
+        self.send_request(request.set_request_id(request_id)).await
+    }
+
+    fn ping() -> Request {
+        Get::DeviceInfo.into()
+    }
+}
+
+/// Opens a new link, None if that (or authenticating) failed
+pub(crate) type Opener<L> = Box<dyn Fn() -> Pin<Box<dyn Future<Output = Option<L>> + Send>> + Send + Sync>;
+
+/// A request for the connection task, answered on `reply`
+struct Exchange<L: Link> {
+    request:    L::Request,
+    deadline:   Instant,    // When the caller stops waiting, the exchange is abandoned then too
+    reply:      oneshot::Sender<Result<L::Response, Error>>,
+}
+
+/// Handle to the task that owns a device's ECP-2 connection, shared between clones of the device
+// The task stops once every clone of the device (and so every Session) has been dropped
+pub(crate) struct Session<L: Link = Connection> {
+    requests:   mpsc::Sender<Exchange<L>>,
+}
+
+impl<L: Link> Clone for Session<L> {
+    fn clone(&self) -> Self {
+        Session { requests: self.requests.clone() }
+    }
+}
+
+impl<L: Link> std::fmt::Debug for Session<L> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Session").finish_non_exhaustive()
+    }
+}
+
+impl<L: Link> Session<L> {
+    /// Start a connection task on an open link, reopening it with `open` whenever it dies
+    pub(crate) fn start(link: L, open: Opener<L>, ipv4: &str, options: ReconnectOptions, timeout: Duration, health: ConnectionHealth) -> Session<L> {
+        let (requests, receiver) = mpsc::channel(32);
+        let task = ConnectionTask {
+            connection: Some(link),
+            open,
+            ipv4: String::from(ipv4),
+            options,
+            timeout,
+            health: health.clone(),
+            last_activity: Instant::now(),
+        };
+        tokio::spawn(task.run(receiver));
+        health.set(ConnectionState::Connected);
+        Session { requests }
+    }
+
+    /// Hand a request to the connection task and wait for its response, for at most `timeout` in total
+    pub(crate) async fn request(&self, request: L::Request, timeout: Duration) -> Result<L::Response, Error> {
+        let deadline = Instant::now() + timeout;
+        let (reply, response) = oneshot::channel();
+        let exchange = Exchange { request, deadline, reply };
+        let answered = async {
+            self.requests.send(exchange).await.map_err(|_| Error::NotConnected)?;
+            response.await.unwrap_or(Err(Error::NotConnected))
+        };
+        tokio::time::timeout_at(deadline, answered).await.unwrap_or(Err(Error::Timeout))
+    }
+
+    /// Whether both handles lead to the same connection task
+    pub(crate) fn same(&self, other: &Session<L>) -> bool {
+        self.requests.same_channel(&other.requests)
+    }
+}
+
+/// Owns a device's ECP-2 connection: sends requests one at a time, pings it while idle and re-establishes it when it dies
+// ecp::Connection only has one exchange in flight, so requests from every clone of the device queue up here
+struct ConnectionTask<L: Link> {
+    connection:     Option<L>,          // None once the connection died and couldn't be re-established
+    open:           Opener<L>,          // Reopens the connection with the stored address and key
+    #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+    ipv4:           String,             // Device address, for metrics
+    options:        ReconnectOptions,
+    timeout:        Duration,           // How long keepalive pings may take
+    health:         ConnectionHealth,
+    last_activity:  Instant,            // When the connection last answered (or was last checked on)
+}
+
+impl<L: Link> ConnectionTask<L> {
+    /// Answer requests until every Session is dropped, pinging the connection whenever it goes idle
+    async fn run(mut self, mut requests: mpsc::Receiver<Exchange<L>>) {
+        loop {
+            let idle = tokio::time::sleep_until(self.last_activity + self.options.keepalive);
+            tokio::select! {
+                exchange = requests.recv() => match exchange {
+                    Some(exchange) => self.answer(exchange).await,
+                    None => break,
+                },
+                _ = idle => self.keepalive().await,
+            }
+        }
+    }
+
+    /// Send a request, reconnecting first if there's no connection and once more if it died
+    async fn answer(&mut self, exchange: Exchange<L>) {
+        // Nobody is waiting for this one any more, so it never reaches the device
+        if exchange.reply.is_closed() || Instant::now() >= exchange.deadline {
+            return;
+        }
+        if self.connection.is_none() && !self.reconnect_by(exchange.deadline).await {
+            let _ = exchange.reply.send(Err(Error::NotConnected));
+            return;
+        }
+
+        let result = match self.send(exchange.request.clone(), exchange.deadline).await {
+            // Timeouts aren't retried, and neither is anything the caller already gave up on
+            Err(Error::NoResponse) if !exchange.reply.is_closed() && self.reconnect_by(exchange.deadline).await => {
+                self.send(exchange.request, exchange.deadline).await
+            }
+            result => result,
+        };
+        let timed_out = matches!(result, Err(Error::Timeout));
+        let _ = exchange.reply.send(result);
+
+        // The stuck connection was dropped, bring it back now so the next request doesn't pay for it
+        if timed_out {
+            self.reconnect().await;
+        }
+    }
+
+    /// Check the connection still answers, re-establishing it if not
+    // A connection that already failed gets another round of attempts, in case the device came back
+    async fn keepalive(&mut self) {
+        let alive = self.connection.is_some() && self.send(L::ping(), Instant::now() + self.timeout).await.is_ok();
+        if !alive {
+            self.reconnect().await;
+        }
+        self.last_activity = Instant::now();
+    }
+
+    /// Send a request on the current connection, giving up at the deadline
+    async fn send(&mut self, request: L::Request, deadline: Instant) -> Result<L::Response, Error> {
+        let connection = self.connection.as_mut().ok_or(Error::NotConnected)?;
+
+        #[cfg(feature = "metrics")]
+        let started = Instant::now();
+        let result = match tokio::time::timeout_at(deadline, connection.send(request)).await {
+            Ok(Some(response)) => {
+                self.last_activity = Instant::now();
+                Ok(response)
+            }
+            Ok(None) => Err(Error::NoResponse),
+            Err(_) => {
+                // Stuck, and a response that still arrives would be paired with the next request. Start over on
+                // a fresh connection before sending anything else.
+                self.connection = None;
+                self.health.set(ConnectionState::Reconnecting { attempt: 0 });
+                Err(Error::Timeout)
+            }
+        };
+        #[cfg(feature = "metrics")]
+        crate::Metrics::global().record_request(&self.ipv4, "ecp2", started.elapsed(), &result);
+        result
+    }
+
+    /// Re-establish the connection, reporting Failed if that takes past the deadline
+    async fn reconnect_by(&mut self, deadline: Instant) -> bool {
+        match tokio::time::timeout_at(deadline, self.reconnect()).await {
+            Ok(reconnected) => reconnected,
+            Err(_) => {
+                self.health.set(ConnectionState::Failed);
+                false
+            }
+        }
+    }
+
+    /// Re-establish the connection with the stored key, backing off exponentially between attempts
+    async fn reconnect(&mut self) -> bool {
+        let mut backoff = self.options.initial_backoff;
+        for attempt in 1..=self.options.max_attempts {
+            if attempt > 1 {
+                tokio::time::sleep(backoff).await;
+                backoff = (backoff * 2).min(self.options.max_backoff);
+            }
+            #[cfg(feature = "metrics")]
+            crate::Metrics::global().record_reconnect(&self.ipv4);
+            self.health.set(ConnectionState::Reconnecting { attempt });
+            if let Some(connection) = (self.open)().await {
+                self.connection = Some(connection);
+                self.last_activity = Instant::now();
+                self.health.set(ConnectionState::Connected);
+                return true;
+            }
+        }
+
+        // Out of attempts, drop the dead connection so callers see NotConnected
+        self.connection = None;
+        self.health.set(ConnectionState::Failed);
+        false
+    }
+}
+
+/// Open a new ECP-2 connection, returning it if authentication succeeded within the timeout
+async fn open(ipv4: String, key: Vec<u8>, timeout: Duration) -> Option<Connection> {
+    let octets: Vec<&str> = ipv4.split('.').collect();
+    let ipv4: [u8; 4] = [
+        octets[0].parse::<u8>().unwrap_or(0),
+        octets[1].parse::<u8>().unwrap_or(0),
+        octets[2].parse::<u8>().unwrap_or(0),
+        octets[3].parse::<u8>().unwrap_or(0),
+    ];
+    let mut connection = Connection::new(ipv4, key);
+    if tokio::time::timeout(timeout, connection.open()).await.is_err() {
+        return None;
+    }
+
+    if connection.is_authenticated() {
+        Some(connection)
+    } else {
+        None
+    }
+}
+
+impl Device {
+    /// Current state of the ECP-2 connection
+    pub fn connection_state(&self) -> ConnectionState {
+        self.health.get()
+    }
+
+    /// Observe connection state changes (e.g. to show Reconnecting in a UI)
+    pub fn watch_connection(&self) -> watch::Receiver<ConnectionState> {
+        self.health.state.subscribe()
+    }
+
+    /// Check the connection still answers
+    pub async fn ping(&mut self) -> bool {
+        self.send_with_reconnect(Get::DeviceInfo.into()).await.is_ok()
+    }
+
+    /// Send a request through the connection task, which reconnects if the connection died
+    // The timeout covers everything: waiting behind other requests, reconnecting and the exchange itself
+    pub(crate) async fn send_with_reconnect(&mut self, request: Request) -> Result<Response, Error> {
+        if !self.is_connected() {
+            return Err(Error::NotConnected);
+        }
+        let session = self.session.as_ref().ok_or(Error::NotConnected)?;
+        session.request(request, self.timeout).await
+    }
+
+    /// Open and authenticate a connection, then hand it to a new connection task
+    pub(crate) async fn open_connection(&mut self, key: Vec<u8>) -> bool {
+        self.health.set(ConnectionState::Connecting);
+        let connection = match open(self.ipv4.clone(), key.clone(), self.timeout).await {
+            Some(connection) => connection,
+            None => return false,
+        };
+
+        let (ipv4, timeout) = (self.ipv4.clone(), self.timeout);
+        let reopen: Opener<Connection> = Box::new(move || Box::pin(open(ipv4.clone(), key.clone(), timeout)));
+        self.session = Some(Session::start(connection, reopen, &self.ipv4, self.reconnect.clone(), self.timeout, self.health.clone()));
+        true
+    }
+}
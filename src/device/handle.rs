@@ -0,0 +1,167 @@
+use crate::{App, Button, Device, DeviceInfo, Error, SearchQuery};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// Cloneable handle to a device that can be used from many tasks at once
+///
+/// Every call shares the device's one ECP-2 connection. Its connection task sends their requests one at a time,
+/// in the order they arrive, and hands each response back to the task that asked. Plain ECP (HTTP) requests
+/// don't queue behind each other.
+// NOTE: Calls run on a copy of the device. Afterwards only the fields a call changed (info, power_state, address,
+// session, ...) are copied back, so concurrent calls don't undo each other
+#[derive(Clone)]
+pub struct DeviceHandle {
+    device: Arc<Mutex<Device>>,
+}
+
+impl Device {
+    /// Turn this device into a handle that can be shared between tasks
+    pub fn share(self) -> DeviceHandle {
+        DeviceHandle::from(self)
+    }
+}
+
+impl From<Device> for DeviceHandle {
+    fn from(device: Device) -> Self {
+        DeviceHandle { device: Arc::new(Mutex::new(device)) }
+    }
+}
+
+impl DeviceHandle {
+    /// Copy of the device sharing its connection, for anything the handle doesn't wrap
+    pub fn device(&self) -> Device {
+        self.lock().clone()
+    }
+
+    /// Keep what a call learned about the device, leaving alone anything it didn't change
+    fn update(&self, before: &Device, after: Device) {
+        let mut shared = self.lock();
+        if after.info != before.info {
+            shared.info = after.info;
+        }
+        if after.power_state != before.power_state {
+            shared.power_state = after.power_state;
+        }
+        if (&after.ipv4, after.port) != (&before.ipv4, before.port) {
+            shared.ipv4 = after.ipv4;
+            shared.port = after.port;
+        }
+        if after.name != before.name {
+            shared.name = after.name;
+        }
+        if after.network != before.network {
+            shared.network = after.network;
+        }
+        if (after.mac_eth, after.mac_wlan) != (before.mac_eth, before.mac_wlan) {
+            shared.mac_eth = after.mac_eth;
+            shared.mac_wlan = after.mac_wlan;
+        }
+        if after.key != before.key {
+            shared.key = after.key;
+        }
+        let same_session = match (&after.session, &before.session) {
+            (Some(a), Some(b)) => a.same(b),
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        if !same_session {
+            shared.session = after.session;
+        }
+    }
+
+    /// The shared device, even if a task panicked while holding it (it's only ever cloned or replaced)
+    fn lock(&self) -> MutexGuard<'_, Device> {
+        self.device.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Press a button on the remote
+    pub async fn press_button(&self, button: Button) -> Result<(), Error> {
+        let before = self.device();
+        let mut device = before.clone();
+        let result = device.press_button(button).await;
+        self.update(&before, device);
+        result
+    }
+
+    /// Send multiple button presses, paced with the default options
+    pub async fn press_buttons(&self, buttons: Vec<Button>) -> Result<(), Error> {
+        let before = self.device();
+        let mut device = before.clone();
+        let result = device.press_buttons(buttons).await;
+        self.update(&before, device);
+        result
+    }
+
+    /// Type text into an open on-screen keyboard
+    pub async fn type_text(&self, text: &str) -> Result<(), Error> {
+        let before = self.device();
+        let mut device = before.clone();
+        let result = device.type_text(text).await;
+        self.update(&before, device);
+        result
+    }
+
+    /// Launch an app by its id
+    pub async fn launch_app_by_id(&self, app_id: i32) -> Result<(), Error> {
+        self.device().launch_app_by_id(app_id).await
+    }
+
+    /// Find an installed app by its id or name
+    pub async fn find_app(&self, app: &str) -> Result<App, Error> {
+        self.device().find_app(app).await
+    }
+
+    /// Launch an installed app by its id or name, returning the app that was launched
+    pub async fn launch_app(&self, app: &str) -> Result<App, Error> {
+        self.device().launch_app(app).await
+    }
+
+    /// Get typed device-info
+    pub async fn get_device_info(&self) -> Result<DeviceInfo, Error> {
+        let before = self.device();
+        let mut device = before.clone();
+        let result = device.get_device_info().await;
+        self.update(&before, device);
+        result
+    }
+
+    /// Get list of installed apps
+    pub async fn get_installed_apps(&self) -> Result<Vec<App>, Error> {
+        self.device().get_installed_apps().await
+    }
+
+    /// Get the app currently in the foreground
+    pub async fn get_active_app(&self) -> Result<Option<App>, Error> {
+        self.device().get_active_app().await
+    }
+
+    /// Open the device's search UI with a structured query
+    pub async fn search(&self, query: SearchQuery) -> Result<(), Error> {
+        self.device().search(query).await
+    }
+
+    /// Turn the screen on
+    pub async fn power_on(&self) -> Result<bool, Error> {
+        let before = self.device();
+        let mut device = before.clone();
+        let result = device.power_on().await;
+        self.update(&before, device);
+        result
+    }
+
+    /// Turn the screen off
+    pub async fn power_off(&self) -> Result<bool, Error> {
+        let before = self.device();
+        let mut device = before.clone();
+        let result = device.power_off().await;
+        self.update(&before, device);
+        result
+    }
+
+    /// Toggle the screen on or off
+    pub async fn toggle_power_state(&self) -> Result<bool, Error> {
+        let before = self.device();
+        let mut device = before.clone();
+        let result = device.toggle_power_state().await;
+        self.update(&before, device);
+        result
+    }
+}
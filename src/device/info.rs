@@ -0,0 +1,106 @@
+use crate::device::parse_device_info;
+use crate::{Button, Device, Error, NetworkType, PowerState};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Typed view of a device's device-info response
+// Only the keys koru cares about are typed, everything is still available in `raw`
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct DeviceInfo {
+    pub friendly_name:          String,                     // friendly-device-name
+    pub serial_number:          String,                     // serial-number
+    pub model_name:             String,                     // model-name
+    pub software_version:       String,                     // software-version
+    pub power_mode:             PowerState,                 // power-mode (e.g. PowerOn, DisplayOff)
+    pub network_type:           Option<NetworkType>,        // network-type
+    pub uptime:                 Option<u64>,                // uptime, in seconds
+    pub is_tv:                  bool,                       // is-tv
+    pub is_stick:               bool,                       // is-stick
+    pub supports_ethernet:      bool,                       // supports-ethernet
+    pub supports_find_remote:   bool,                       // supports-find-remote
+    pub supports_suspend:       bool,                       // supports-suspend
+    pub search_enabled:         bool,                       // search-enabled
+    pub voice_search_enabled:   bool,                       // voice-search-enabled
+    pub raw:                    HashMap<String, String>,    // Every key/value, as reported
+}
+
+impl From<HashMap<String, String>> for DeviceInfo {
+    fn from(raw: HashMap<String, String>) -> Self {
+        let text = |key: &str| raw.get(key).cloned().unwrap_or_default();
+        let flag = |key: &str| raw.get(key).map(|v| v.eq_ignore_ascii_case("true")).unwrap_or(false);
+
+        DeviceInfo {
+            friendly_name: text("friendly-device-name"),
+            serial_number: text("serial-number"),
+            model_name: text("model-name"),
+            software_version: text("software-version"),
+            power_mode: raw.get("power-mode").map(|v| PowerState::from(v.as_str())).unwrap_or_default(),
+            network_type: raw.get("network-type").map(|v| NetworkType::from(v.as_str())),
+            uptime: raw.get("uptime").and_then(|v| u64::from_str(v).ok()),
+            is_tv: flag("is-tv"),
+            is_stick: flag("is-stick"),
+            supports_ethernet: flag("supports-ethernet"),
+            supports_find_remote: flag("supports-find-remote"),
+            supports_suspend: flag("supports-suspend"),
+            search_enabled: flag("search-enabled"),
+            voice_search_enabled: flag("voice-search-enabled"),
+            raw,
+        }
+    }
+}
+
+impl DeviceInfo {
+    /// Whether a device with this info should respond to a button
+    // Only buttons device-info has a flag for are refused, anything else (e.g. the numpad and Guide,
+    // which depend on the remote and tuner rather than the device type) is assumed to work
+    pub fn supports_button(&self, button: &Button) -> bool {
+        match button {
+            // Only some remotes can be located
+            Button::FindRemote => self.supports_find_remote,
+            // Volume and power keys only do something on TVs (or players that can suspend)
+            Button::VolumeDown | Button::VolumeMute | Button::VolumeUp => self.is_tv,
+            Button::Power | Button::PowerOff | Button::PowerOn => self.is_tv || self.supports_suspend,
+            // Only TVs have inputs to switch between
+            Button::ChannelUp | Button::ChannelDown | Button::InputTuner
+            | Button::InputHdmi1 | Button::InputHdmi2 | Button::InputHdmi3 | Button::InputHdmi4 | Button::InputAv1 => self.is_tv,
+            // Search keys depend on search being enabled
+            Button::TextSearch => self.search_enabled,
+            Button::VoiceSearch => self.voice_search_enabled,
+            // Everything else is on every remote
+            _ => true,
+        }
+    }
+
+    /// Every named button a device with this info should respond to
+    pub fn supported_buttons(&self) -> Vec<Button> {
+        Button::ALL.iter()
+            .filter(|b| self.supports_button(b))
+            .cloned()
+            .collect()
+    }
+}
+
+impl Device {
+    /// Fetch typed device-info, caching it for capability checks
+    pub async fn get_device_info(&mut self) -> Result<DeviceInfo, Error> {
+        let info = DeviceInfo::from(self.get_info().await?);
+        self.info = Some(info.clone());
+        Ok(info)
+    }
+
+    /// Fetch typed device-info over plain ECP (HTTP), which doesn't need an ECP-2 connection or key
+    pub async fn query_device_info(&self) -> Result<DeviceInfo, Error> {
+        let xml = self.fetch("query/device-info").await?;
+        Ok(DeviceInfo::from(parse_device_info(&xml)))
+    }
+
+    /// Named buttons this device supports, fetching device-info if it isn't cached yet
+    pub async fn supported_buttons(&mut self) -> Result<Vec<Button>, Error> {
+        match &self.info {
+            Some(info) => Ok(info.supported_buttons()),
+            None => Ok(self.get_device_info().await?.supported_buttons()),
+        }
+    }
+}
@@ -1,37 +1,79 @@
+mod connection;
+mod handle;
+mod info;
 mod network;
 mod power;
+mod timeout;
+mod wake;
+mod watch;
+mod wol;
 
 use ecp::{ContentData, Get, Request, Response, Set};
 
+pub use crate::device::connection::{ConnectionState, ReconnectOptions, Transport};
+pub use crate::device::handle::DeviceHandle;
+pub use crate::device::info::DeviceInfo;
 pub use crate::device::network::NetworkType;
 pub use crate::device::power::PowerState;
+pub use crate::device::timeout::WithTimeout;
+pub use crate::device::watch::{DeviceEvent, DeviceWatcher, EventKind, MediaState};
+pub(crate) use crate::device::connection::{ConnectionHealth, Session};
+#[cfg(test)]
+pub(crate) use crate::device::connection::{Link, Opener};
+pub(crate) use crate::device::watch::Snapshot;
+pub use crate::device::wol::WakeOnLanOptions;
 
-use ecp::Connection;
 use std::collections::HashMap;
 use quick_xml::{Reader, events::Event};
-use crate::App;
+use crate::{App, Error};
 use std::ops::Deref;
 use std::str::FromStr;
+use std::time::Duration;
+
+// How long requests may take unless the device's timeout is changed
+pub(crate) const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Device object
+// Clones share the ECP-2 connection. With the serde feature, it (and the key it was opened with) is never serialized
 #[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Device {
-    pub connection:     Option<Connection>, // ECP-2 connection
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) session: Option<Session>,    // Task owning the ECP-2 connection
     pub ipv4:           String,             // IPv4 address
     pub port:           i32,                // Port (Default: 8060)
+    #[cfg_attr(feature = "serde", serde(default))]
     pub name:           String,             // Device name
     pub network:        NetworkType,        // Connected network type
+    #[cfg_attr(feature = "serde", serde(default, with = "crate::serialize::mac"))]
     pub mac_wol:        Option<[u8; 6]>,    // MAC address used for Wake-on-LAN (if supported)
+    #[cfg_attr(feature = "serde", serde(default, with = "crate::serialize::mac"))]
     pub mac_wlan:       Option<[u8; 6]>,    // MAC address for WLAN
+    #[cfg_attr(feature = "serde", serde(default, with = "crate::serialize::mac"))]
     pub mac_eth:        Option<[u8; 6]>,    // MAC address for Ethernet
+    #[cfg_attr(feature = "serde", serde(default))]
     pub power_state:    PowerState,         // Last-known device power state
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub info:           Option<DeviceInfo>, // Last-fetched device-info, used for capability checks
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub wol:            WakeOnLanOptions,   // Where to send Wake-on-LAN packets
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub transport:      Transport,          // Whether commands go over ECP-2 or plain ECP (HTTP)
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub reconnect:      ReconnectOptions,   // Keepalive and reconnect backoff for the ECP-2 connection
+    #[cfg_attr(feature = "serde", serde(default = "crate::serialize::default_timeout"))]
+    pub timeout:        Duration,           // How long any single request may take (see with_timeout)
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) key:     Option<Vec<u8>>,    // ECP-2 key from the last connect, for reconnecting
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub(crate) health:  ConnectionHealth,   // Connection state and last response time
 }
 
 impl Device {
     /// Constructor w/ only IPv4 and port
     pub fn new(ipv4: &str, port: i32) -> Device {
         Device {
-            connection: None,
+            session: None,
             ipv4: String::from(ipv4),
             port,
             name: "".to_string(),
@@ -40,220 +82,392 @@ impl Device {
             mac_wlan: None,
             mac_eth: None,
             power_state: PowerState::Unknown,
+            info: None,
+            wol: WakeOnLanOptions::default(),
+            transport: Transport::default(),
+            reconnect: ReconnectOptions::default(),
+            timeout: DEFAULT_TIMEOUT,
+            key: None,
+            health: ConnectionHealth::default(),
         }
     }
 
     /// Open an ECP-2 connection to the device and authenticate, returning auth result
     pub async fn connect(&mut self, key: Vec<u8>) -> bool {
-        let octets: Vec<&str> = self.ipv4.split('.').collect();
-        let ipv4: [u8; 4] = [
-            octets[0].parse::<u8>().unwrap_or(0),
-            octets[1].parse::<u8>().unwrap_or(0),
-            octets[2].parse::<u8>().unwrap_or(0),
-            octets[3].parse::<u8>().unwrap_or(0),
-        ];
-        let mut connection = Connection::new(ipv4, key);
-        connection.open().await;
-
-        if connection.is_authenticated() {
-            self.connection = Some(connection);
-        }
+        // Remember the key so the connection can be re-established later
+        self.key = Some(key.clone());
 
-        match self.connection {
-            None => false,
-            Some(_) => true,
+        if self.open_connection(key).await {
+            return true;
         }
+        // Authentication failed, but an earlier connection may still be open
+        self.health.set(match self.session.is_some() {
+            true => ConnectionState::Connected,
+            false => ConnectionState::Failed,
+        });
+        self.is_connected()
     }
 
-    /// Whether the connection has been opened
+    /// Whether the connection has been opened and hasn't failed since
     pub fn is_connected(&self) -> bool {
-        match self.connection {
-            None => false,
-            Some(_) => true,
-        }
+        self.session.is_some() && self.connection_state() != ConnectionState::Failed
     }
 
     /// Send an arbitrary request to the device and return the response
     pub async fn send_request(&mut self, request: Request) -> Option<Response> {
-        let request_id = self.next_sync_number();
-        if let Some(connection) = &mut self.connection {
-            let message = request.set_request_id(request_id);
-            connection.send_request(message).await
-        }
-        else {
-            None
-        }
+        self.request(request).await.ok()
+    }
+
+    /// Send an arbitrary request to the device, failing with Error::Timeout if it takes longer than self.timeout
+    pub async fn request(&mut self, request: Request) -> Result<Response, Error> {
+        self.send_with_reconnect(request).await
     }
 
     /// Return parsed device-info XML
-    pub async fn get_info(&mut self) -> Result<HashMap<String, String>, String> {
+    pub async fn get_info(&mut self) -> Result<HashMap<String, String>, Error> {
         if !self.is_connected() {
-            return Err(String::from("Not connected to device"));
+            return Err(Error::NotConnected);
         }
 
-        if let Some(message) = self.send_request(Get::DeviceInfo.into()).await {
-            if let Some(ContentData::Text { string: xml }) = message.content_data {
-                // Parsed XML keys/values
-                let mut xml_parsed: HashMap<String, String> = HashMap::new();
-                // Create XML reader
-                let mut reader = Reader::from_str(&xml);
-                reader.trim_text(true);
-                // XML event buffer
-                let mut buffer = Vec::new();
-                // Current tag
-                let mut tag = String::new();
-                // Loop the XML
-                loop {
-                    match reader.read_event(&mut buffer) {
-                        // Read each tag
-                        Ok(Event::Start(ref e)) => tag = std::str::from_utf8(e.name()).unwrap_or("").to_string(),
-                        // Handle tag content
-                        Ok(Event::Text(e)) => {
-                            // Skip working with top-level tags
-                            if tag != "?xml" && tag != "device-info" {
-                                // Create new entry in hashmap
-                                xml_parsed.insert(
-                                    tag.clone(),
-                                    e.unescape_and_decode(&reader).unwrap_or(String::new())
-                                );
-                            }
-                        },
-                        // Break at EOF
-                        Ok(Event::Eof) => break,
-                        Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
-                        _ => (),
-                    }
-                    buffer.clear();
-                }
-                // Return hashmap of xml
-                return Ok(xml_parsed);
-            }
-
-            return Err(String::from("Response had no content"))
+        let message = self.request(Get::DeviceInfo.into()).await?;
+        if let Some(ContentData::Text { string: xml }) = message.content_data {
+            return Ok(parse_device_info(&xml));
         }
 
-        Err(String::from("Empty response received"))
+        Err(Error::EmptyResponse)
     }
 
     /// Get list of installed apps
-    pub async fn get_installed_apps(&mut self) -> Result<Vec<App>, String> {
+    pub async fn get_installed_apps(&mut self) -> Result<Vec<App>, Error> {
+        if self.transport == Transport::Http {
+            return parse_installed_apps(&self.fetch("query/apps").await?);
+        }
         if !self.is_connected() {
-            return Err(String::from("Not connected to device"));
+            return Err(Error::NotConnected);
         }
 
-        if let Some(message) = self.send_request(Get::InstalledApps.into()).await {
-            if let Some(ContentData::Text { string: xml }) = message.content_data {
-                // Parsed XML keys/values
-                let mut apps_parsed: Vec<App> = Vec::new();
-                // Create XML reader
-                let mut reader = Reader::from_str(&xml);
-                reader.trim_text(true);
-                // XML event buffer
-                let mut buffer = Vec::new();
-                // Whether to read tag content
-                let mut read = false;
-                // Current roku app from tag
-                let mut app = App {
-                    id: 0,
-                    apptype: "".to_string(),
-                    version: "".to_string(),
-                    name: "".to_string(),
-                    icon: None
-                };
-                // Loop the XML
-                loop {
-                    match reader.read_event(&mut buffer) {
-                        // Read each tag
-                        Ok(Event::Start(ref e)) => {
-                            if e.name() != b"?xml" && e.name() != b"apps" {
-                                // Parse and collect attributes
-                                let attributes = e.attributes()
-                                    .map(|a| a.unwrap().value)
-                                    .collect::<Vec<_>>();
-                                // Create RokuApp object from attributes
-                                app = App {
-                                    id: i32::from_str(&std::str::from_utf8(attributes[0].deref()).unwrap_or("").to_string()).unwrap(),
-                                    apptype: std::str::from_utf8(attributes[1].deref()).unwrap_or("").to_string(),
-                                    version: std::str::from_utf8(attributes[2].deref()).unwrap_or("").to_string(),
-                                    name: String::new(),
-                                    icon: None
-                                };
-                                // Prepare to read tag content
-                                read = true;
-                            }
-                        },
-                        // Handle tag content
-                        Ok(Event::Text(e)) => {
-                            // Skip working with top-level tags
-                            if read {
-                                // Update currently-parsed app name
-                                app.name = e.unescape_and_decode(&reader)
-                                    .unwrap_or(String::new())
-                                    .replace("\u{a0}", "");     // There are newline characters in some names
-                                // Add app to list of parsed apps
-                                apps_parsed.push(app.clone());
-                            }
-                        },
-                        // Break at EOF
-                        Ok(Event::Eof) => break,
-                        Err(e) => panic!("Error at position {}: {:?}", reader.buffer_position(), e),
-                        _ => (),
-                    }
-                    buffer.clear();
-                }
-                // Return list of apps
-                return Ok(apps_parsed)
-            }
-
-            return Err(String::from("Response had no content"));
+        let message = self.request(Get::InstalledApps.into()).await?;
+        if let Some(ContentData::Text { string: xml }) = message.content_data {
+            return parse_installed_apps(&xml);
         }
 
-        Err(String::from("Empty response received"))
+        Err(Error::EmptyResponse)
+    }
+
+    /// Get the app currently in the foreground, or None when on the home screen
+    pub async fn get_active_app(&self) -> Result<Option<App>, Error> {
+        let xml = self.fetch("query/active-app").await?;
+        Ok(parse_active_app(&xml))
     }
 
     /// Launch an app by its id
-    pub async fn launch_app_by_id(&mut self, app_id: i32) {
-        self.send_request(Set::LaunchApp { channel_id: app_id }.into()).await;
+    pub async fn launch_app_by_id(&mut self, app_id: i32) -> Result<(), Error> {
+        if self.transport == Transport::Http {
+            return self.post(&format!("launch/{}", app_id)).await;
+        }
+        self.request(Set::LaunchApp { channel_id: app_id }.into()).await.map(|_| ())
+    }
+
+    /// Find an installed app by its id or name (ignoring case)
+    pub async fn find_app(&mut self, app: &str) -> Result<App, Error> {
+        self.get_installed_apps().await?.into_iter()
+            .find(|a| a.id.to_string() == app || a.name.eq_ignore_ascii_case(app))
+            .ok_or_else(|| Error::InvalidRequest(format!("No installed app matching \"{}\"", app)))
+    }
+
+    /// Launch an installed app by its id or name, returning the app that was launched
+    pub async fn launch_app(&mut self, app: &str) -> Result<App, Error> {
+        let found = self.find_app(app).await?;
+        self.launch_app_by_id(found.id).await?;
+        Ok(found)
     }
 
     /// Manually update this object to match real-world device
     pub async fn update_self(&mut self) {
         // Attempt to get complete device info (we currently only have IP & port)
         if let Ok(info) = self.get_info().await {
+            // Keep the typed copy around for capability checks
+            self.info = Some(DeviceInfo::from(info.clone()));
             // Update device object with new info using the hashmap
             self.name = info.get("friendly-device-name").unwrap().clone();
             self.network = NetworkType::from(info.get("network-type").unwrap().clone().to_ascii_uppercase());
-            self.mac_wlan = Some(split_mac(info.get("wifi-mac").unwrap()));
+            self.mac_wlan = parse_mac(info.get("wifi-mac").unwrap());
             // Handle failing to resolve this from the hashmap (do devices w/o support still have it?)
             if let Some(support) = info.get("supports-ethernet") {
                 // Check if this device supports ethernet
                 if support.to_ascii_uppercase().as_str() == "TRUE" {
                     // Parse the Ethernet MAC
-                    self.mac_eth = Some(split_mac(info.get("ethernet-mac").unwrap_or(&"0:0:0:0:0:0".to_string())));
+                    self.mac_eth = info.get("ethernet-mac").and_then(|mac| parse_mac(mac));
                 }
             }
         }
     }
 
-    /// Get the next message sync number
-    fn next_sync_number(&mut self) -> i32 {
-        if let Some(connection) = &mut self.connection {
-            connection.next_sync_number()
+    /// Build an ECP (HTTP) endpoint URL for this device
+    pub(crate) fn endpoint(&self, path: &str) -> String {
+        format!("http://{}:{}/{}", self.ipv4, self.port, path)
+    }
+
+    /// Send a POST to an ECP (HTTP) endpoint on this device
+    pub(crate) async fn post(&self, path: &str) -> Result<(), Error> {
+        #[cfg(feature = "metrics")]
+        let started = tokio::time::Instant::now();
+        let result = http_post(&self.endpoint(path), self.timeout).await;
+        #[cfg(feature = "metrics")]
+        crate::Metrics::global().record_request(&self.ipv4, "http", started.elapsed(), &result);
+        result
+    }
+
+    /// Send a GET to an ECP (HTTP) endpoint on this device and return the response body
+    pub(crate) async fn fetch(&self, path: &str) -> Result<String, Error> {
+        let url = self.endpoint(path);
+        #[cfg(feature = "metrics")]
+        let started = tokio::time::Instant::now();
+        let result = match http_client(self.timeout).get(&url).send().await {
+            Ok(response) if !response.status().is_success() => {
+                Err(Error::Http(format!("{} returned status {}", url, response.status())))
+            }
+            Ok(response) => response.text().await.map_err(|e| http_error(&url, e)),
+            Err(e) => Err(http_error(&url, e)),
+        };
+        #[cfg(feature = "metrics")]
+        crate::Metrics::global().record_request(&self.ipv4, "http", started.elapsed(), &result);
+        result
+    }
+}
+
+/// Parse a device-info response into its keys/values
+pub(crate) fn parse_device_info(xml: &str) -> HashMap<String, String> {
+    // Parsed XML keys/values
+    let mut xml_parsed: HashMap<String, String> = HashMap::new();
+    // Create XML reader
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    // XML event buffer
+    let mut buffer = Vec::new();
+    // Current tag
+    let mut tag = String::new();
+    // Loop the XML
+    loop {
+        match reader.read_event(&mut buffer) {
+            // Read each tag
+            Ok(Event::Start(ref e)) => tag = std::str::from_utf8(e.name()).unwrap_or("").to_string(),
+            // Handle tag content
+            Ok(Event::Text(e)) => {
+                // Skip working with top-level tags
+                if tag != "?xml" && tag != "device-info" {
+                    // Create new entry in hashmap
+                    xml_parsed.insert(
+                        tag.clone(),
+                        e.unescape_and_decode(&reader).unwrap_or(String::new())
+                    );
+                }
+            },
+            // Break at EOF
+            Ok(Event::Eof) => break,
+            // Stop at malformed XML, keeping whatever was parsed so far
+            Err(_) => break,
+            _ => (),
         }
-        else {
-            0
+        buffer.clear();
+    }
+    // Return hashmap of xml
+    xml_parsed
+}
+
+/// Parse a query/apps response into the installed apps
+pub(crate) fn parse_installed_apps(xml: &str) -> Result<Vec<App>, Error> {
+    // Parsed XML keys/values
+    let mut apps_parsed: Vec<App> = Vec::new();
+    // Create XML reader
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    // XML event buffer
+    let mut buffer = Vec::new();
+    // Whether to read tag content
+    let mut read = false;
+    // Current roku app from tag
+    let mut app = App {
+        id: 0,
+        apptype: "".to_string(),
+        version: "".to_string(),
+        name: "".to_string(),
+        icon: None
+    };
+    // Loop the XML
+    loop {
+        match reader.read_event(&mut buffer) {
+            // Read each tag
+            Ok(Event::Start(ref e)) => {
+                if e.name() != b"?xml" && e.name() != b"apps" {
+                    // Parse and collect attributes
+                    let attributes = e.attributes()
+                        .map(|a| a.map(|a| std::str::from_utf8(a.value.deref()).unwrap_or("").to_string()))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(|e| Error::Parse(format!("Bad attribute at position {}: {:?}", reader.buffer_position(), e)))?;
+                    if attributes.len() < 3 {
+                        return Err(Error::Parse(format!("App at position {} is missing attributes", reader.buffer_position())));
+                    }
+                    // Create RokuApp object from attributes
+                    app = App {
+                        id: i32::from_str(&attributes[0])
+                            .map_err(|_| Error::Parse(format!("Bad app id {:?}", attributes[0])))?,
+                        apptype: attributes[1].clone(),
+                        version: attributes[2].clone(),
+                        name: String::new(),
+                        icon: None
+                    };
+                    // Prepare to read tag content
+                    read = true;
+                }
+            },
+            // Handle tag content
+            Ok(Event::Text(e)) => {
+                // Skip working with top-level tags
+                if read {
+                    // Update currently-parsed app name
+                    app.name = e.unescape_and_decode(&reader)
+                        .unwrap_or(String::new())
+                        .replace("\u{a0}", "");     // There are newline characters in some names
+                    // Add app to list of parsed apps
+                    apps_parsed.push(app.clone());
+                }
+            },
+            // Break at EOF
+            Ok(Event::Eof) => break,
+            Err(e) => return Err(Error::Parse(format!("Error at position {}: {:?}", reader.buffer_position(), e))),
+            _ => (),
         }
+        buffer.clear();
     }
+    // Return list of apps
+    Ok(apps_parsed)
 }
 
-/// Split up device MACs into byte arrays
-fn split_mac(input: &str) -> [u8; 6] {
-    let mut index = 0;
-    let mut output: [u8; 6] = [0; 6];
-    str::split(input, ':')
-        .for_each(|chunk| {
-            output[index] = u8::from_str_radix(chunk, 16).unwrap();
-            index += 1;
-        });
-    output
+/// Parse a query/active-app response
+// The home screen shows up as an <app> without an id, which we treat as no app
+pub(crate) fn parse_active_app(xml: &str) -> Option<App> {
+    // Create XML reader
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    // XML event buffer
+    let mut buffer = Vec::new();
+    // App from the <app> tag, if it had an id
+    let mut app: Option<App> = None;
+    // Loop the XML
+    loop {
+        match reader.read_event(&mut buffer) {
+            // Collect attributes from the <app> tag
+            Ok(Event::Start(ref e)) if e.name() == b"app" => {
+                let mut parsed = App { id: 0, apptype: String::new(), version: String::new(), name: String::new(), icon: None };
+                let mut has_id = false;
+                for attribute in e.attributes().flatten() {
+                    let value = std::str::from_utf8(attribute.value.deref()).unwrap_or("").to_string();
+                    match attribute.key {
+                        b"id" => {
+                            if let Ok(id) = i32::from_str(&value) {
+                                parsed.id = id;
+                                has_id = true;
+                            }
+                        }
+                        b"type" => parsed.apptype = value,
+                        b"version" => parsed.version = value,
+                        _ => (),
+                    }
+                }
+                if has_id {
+                    app = Some(parsed);
+                }
+            }
+            // The app name is the tag content
+            Ok(Event::Text(e)) => {
+                if let Some(app) = &mut app {
+                    if app.name.is_empty() {
+                        app.name = e.unescape_and_decode(&reader).unwrap_or_default();
+                    }
+                }
+            }
+            // Only the first <app> is the active one (screensavers come after it)
+            Ok(Event::End(ref e)) if e.name() == b"app" => break,
+            Ok(Event::Eof) | Err(_) => break,
+            _ => (),
+        }
+        buffer.clear();
+    }
+    app
+}
+
+/// Read an attribute from the first occurrence of a tag
+pub(crate) fn xml_attribute(xml: &str, tag: &[u8], attribute: &[u8]) -> Option<String> {
+    // Create XML reader
+    let mut reader = Reader::from_str(xml);
+    reader.trim_text(true);
+    // XML event buffer
+    let mut buffer = Vec::new();
+    // Loop the XML
+    loop {
+        match reader.read_event(&mut buffer) {
+            // Check the first matching tag, whether or not it has content
+            Ok(Event::Start(ref e)) | Ok(Event::Empty(ref e)) if e.name() == tag => {
+                return e.attributes()
+                    .flatten()
+                    .find(|a| a.key == attribute)
+                    .map(|a| std::str::from_utf8(a.value.deref()).unwrap_or("").to_string());
+            }
+            Ok(Event::Eof) | Err(_) => return None,
+            _ => (),
+        }
+        buffer.clear();
+    }
+}
+
+/// HTTP client that gives up on requests after a timeout
+fn http_client(timeout: Duration) -> reqwest::Client {
+    reqwest::Client::builder()
+        .timeout(timeout)
+        .build()
+        .unwrap_or_default()
+}
+
+/// Convert a failed HTTP request into an Error, keeping timeouts and connect failures distinct
+fn http_error(url: &str, e: reqwest::Error) -> Error {
+    if e.is_timeout() {
+        Error::Timeout
+    } else if e.is_connect() {
+        Error::Unreachable(format!("Request to {} failed: {}", url, e))
+    } else {
+        Error::Http(format!("Request to {} failed: {}", url, e))
+    }
+}
+
+/// Send an empty POST to an ECP (HTTP) endpoint, succeeding on any 2xx status
+pub(crate) async fn http_post(url: &str, timeout: Duration) -> Result<(), Error> {
+    match http_client(timeout).post(url).send().await {
+        Ok(response) => {
+            if response.status().is_success() {
+                Ok(())
+            } else {
+                Err(Error::Http(format!("{} returned status {}", url, response.status())))
+            }
+        }
+        Err(e) => Err(http_error(url, e)),
+    }
+}
+
+/// Parse "a.b.c.d" or "a.b.c.d:port", defaulting to the ECP port 8060
+pub fn parse_address(s: &str) -> Option<(std::net::Ipv4Addr, i32)> {
+    match s.split_once(':') {
+        Some((ipv4, port)) => Some((ipv4.parse().ok()?, port.parse().ok()?)),
+        None => Some((s.parse().ok()?, 8060)),
+    }
+}
+
+/// Parse "aa:bb:cc:dd:ee:ff" (or with dashes), rejecting anything else
+pub(crate) fn parse_mac(s: &str) -> Option<[u8; 6]> {
+    let mut mac = [0; 6];
+    let mut parts = s.trim().split([':', '-']);
+    for byte in mac.iter_mut() {
+        *byte = u8::from_str_radix(parts.next().filter(|p| p.len() == 2)?, 16).ok()?;
+    }
+    parts.next().is_none().then_some(mac)
 }
\ No newline at end of file
@@ -1,5 +1,6 @@
 /// Network types a device could be connected to
 #[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum NetworkType {
     Wireless,   // e.g. Wi-Fi
     Ethernet,   // Ethernet cable
@@ -1,14 +1,23 @@
+use crate::{Button, Device, Error};
+use std::time::Duration;
 
-use wake_on_lan::MagicPacket;
-
-use crate::{Button, Device};
+// How long power_on/power_off wait for the device to report the new state
+const POWER_CHECK_TIMEOUT: Duration = Duration::from_secs(5);
+// How long power_on waits for a device woken over LAN to boot and answer again
+const POWER_WAKE_TIMEOUT: Duration = Duration::from_secs(45);
+// How often power_on/power_off check the device's power state
+const POWER_CHECK_INTERVAL: Duration = Duration::from_millis(500);
 
 /// Possible power states for a device to be in
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PowerState {
     Off,        // Powered down, requires wake-on-lan
     DisplayOff, // Screen off, hardware on, still accessible via API
+    Ready,      // Standby, still accessible via API
+    Headless,   // Running without a display (e.g. audio-only), still accessible via API
     On,         // Screen is on
+    #[default]
     Unknown,    // ???
 }
 
@@ -17,6 +26,8 @@ impl From<&str> for PowerState {
         match s.to_ascii_lowercase().as_str() {
             "off" | "poweroff" => PowerState::Off,
             "displayoff" => PowerState::DisplayOff,
+            "ready" | "suspend" => PowerState::Ready,
+            "headless" => PowerState::Headless,
             "on" | "poweron" => PowerState::On,
             _ => PowerState::Unknown
         }
@@ -30,37 +41,93 @@ impl ToString for PowerState {
         match self {
             PowerState::Off => String::from("Off"),
             PowerState::DisplayOff => String::from("DisplayOff"),
+            PowerState::Ready => String::from("Ready"),
+            PowerState::Headless => String::from("Headless"),
             PowerState::On => String::from("On"),
             PowerState::Unknown => String::from("Unknown"),
         }
     }
 }
 
+impl PowerState {
+    /// Whether the screen is off (or the device is fully off)
+    pub fn is_off(&self) -> bool {
+        matches!(self, PowerState::Off | PowerState::DisplayOff | PowerState::Ready | PowerState::Headless)
+    }
+}
+
 impl Device {
-    /// Whether the device support Wake-on-LAN
+    /// Whether the device can be woken over LAN, i.e. there's a MAC to send the magic packet to
     pub fn supports_wake_on_lan(&self) -> bool {
-        match self.mac_wol {
-            None => false,
-            Some(_) => true,
+        self.wake_on_lan_mac().is_some()
+    }
+
+    /// Read the device's power-mode and update the cached power_state
+    // Read over HTTP, which a sleeping device may still answer after its ECP-2 connection dropped.
+    // A device that can't be reached or doesn't answer at all is considered Off, since it'll need Wake-on-LAN.
+    // One that answers with an error status is still on, so that error is returned
+    pub async fn refresh_power_state(&mut self) -> Result<PowerState, Error> {
+        self.power_state = match self.query_device_info().await {
+            Ok(info) => {
+                let state = info.power_mode.clone();
+                self.info = Some(info);
+                state
+            }
+            Err(Error::Unreachable(_) | Error::Timeout | Error::NoResponse | Error::NotConnected) => PowerState::Off,
+            Err(e) => return Err(e),
+        };
+        Ok(self.power_state.clone())
+    }
+
+    /// Turn the screen on, waking the device over LAN if it's unreachable, and report whether it came on
+    pub async fn power_on(&mut self) -> Result<bool, Error> {
+        if self.refresh_power_state().await? == PowerState::Off {
+            // Waking reconnects ECP-2 once the device answers again, which can take a while from cold
+            match self.wake_and_wait(POWER_WAKE_TIMEOUT).await {
+                Ok(_) => {}
+                Err(Error::Timeout) => return Ok(false),
+                Err(e) => return Err(e),
+            }
+        }
+        match self.refresh_power_state().await? {
+            PowerState::On => return Ok(true),
+            PowerState::Off => return Ok(false),
+            _ => self.press_button(Button::PowerOn).await?,
         }
+        Ok(self.wait_for_power(|state| *state == PowerState::On).await)
     }
 
-    /// Toggle the device power state, sending a Wake-on-LAN packet if required
-    pub async fn toggle_power_state(&mut self) {
-        match self.power_state {
-            PowerState::Off => {
-                if !self.supports_wake_on_lan() {
-                    println!("[!] Device is off but doesn't support Wake-on-LAN");
-                    return;
-                }
+    /// Turn the screen off and report whether it went off
+    pub async fn power_off(&mut self) -> Result<bool, Error> {
+        if self.refresh_power_state().await?.is_off() {
+            return Ok(true);
+        }
+        self.press_button(Button::PowerOff).await?;
+        Ok(self.wait_for_power(PowerState::is_off).await)
+    }
 
-                if let Err(e) = MagicPacket::new(&self.mac_wol.unwrap()).send() {
-                    println!("[!] Unable to send Wake-on-LAN: {:?}", e);
+    /// Toggle the device power state, sending a Wake-on-LAN packet if required, and report whether it worked
+    pub async fn toggle_power_state(&mut self) -> Result<bool, Error> {
+        if self.refresh_power_state().await?.is_off() {
+            self.power_on().await
+        } else {
+            self.power_off().await
+        }
+    }
+
+    /// Poll the power state (over HTTP) until it matches, giving up after POWER_CHECK_TIMEOUT
+    async fn wait_for_power(&mut self, done: impl Fn(&PowerState) -> bool) -> bool {
+        let started = tokio::time::Instant::now();
+        loop {
+            if let Ok(state) = self.refresh_power_state().await {
+                if done(&state) {
+                    return true;
                 }
             }
-            _ => {
-                let _ = self.press_button(Button::Power).await;
+            if started.elapsed() >= POWER_CHECK_TIMEOUT {
+                return false;
             }
+            tokio::time::sleep(POWER_CHECK_INTERVAL).await;
         }
     }
-}
\ No newline at end of file
+}
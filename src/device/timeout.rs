@@ -0,0 +1,39 @@
+use crate::Device;
+use std::ops::{Deref, DerefMut};
+use std::time::Duration;
+
+/// A device with a temporary request timeout, restored when this is dropped
+///
+/// e.g. `device.with_timeout(Duration::from_secs(2)).press_button(Button::Home).await`
+pub struct WithTimeout<'a> {
+    device:     &'a mut Device,
+    previous:   Duration,
+}
+
+impl Device {
+    /// Use a different request timeout for the calls made through the returned handle
+    pub fn with_timeout(&mut self, timeout: Duration) -> WithTimeout<'_> {
+        let previous = std::mem::replace(&mut self.timeout, timeout);
+        WithTimeout { device: self, previous }
+    }
+}
+
+impl Deref for WithTimeout<'_> {
+    type Target = Device;
+
+    fn deref(&self) -> &Device {
+        self.device
+    }
+}
+
+impl DerefMut for WithTimeout<'_> {
+    fn deref_mut(&mut self) -> &mut Device {
+        self.device
+    }
+}
+
+impl Drop for WithTimeout<'_> {
+    fn drop(&mut self) {
+        self.device.timeout = self.previous;
+    }
+}
@@ -0,0 +1,81 @@
+use crate::device::parse_device_info;
+use crate::{discover_devices, Device, Error};
+use std::time::Duration;
+
+// How long to wait for the device to answer after each magic packet
+const WAKE_RETRY_INTERVAL: Duration = Duration::from_secs(3);
+// How long a single reachability probe may take
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+// How long to listen for SSDP responses when looking for a device that moved
+const REDISCOVERY_TIMEOUT: Duration = Duration::from_secs(2);
+
+impl Device {
+    /// Whether the device answers ECP requests at its current address
+    pub async fn is_reachable(&self) -> bool {
+        matches!(tokio::time::timeout(PROBE_TIMEOUT, self.fetch("query/device-info")).await, Ok(Ok(_)))
+    }
+
+    /// Wake the device over LAN and wait until it's back, reconnecting and refreshing this object
+    // If the device comes back at a different address (e.g. a new DHCP lease), it's found again via SSDP by MAC or serial
+    pub async fn wake_and_wait(&mut self, timeout: Duration) -> Result<&mut Device, Error> {
+        // Keep sending magic packets until the device answers somewhere
+        let wait = async {
+            let mut attempt = 0;
+            loop {
+                self.send_wake_on_lan()?;
+                attempt += 1;
+                tokio::time::sleep(WAKE_RETRY_INTERVAL).await;
+
+                if self.is_reachable().await {
+                    return Ok(());
+                }
+                // Only look elsewhere every other attempt, since discovery is slow-ish
+                if attempt % 2 == 0 {
+                    if let Some(found) = self.rediscover().await {
+                        self.ipv4 = found.ipv4;
+                        self.port = found.port;
+                        return Ok(());
+                    }
+                }
+            }
+        };
+        match tokio::time::timeout(timeout, wait).await {
+            Ok(result) => result?,
+            Err(_) => return Err(Error::Timeout),
+        }
+
+        // Reconnect with the last key used, if there was one
+        if let Some(key) = self.key.clone() {
+            self.session = None;
+            if !self.connect(key).await {
+                return Err(Error::NotConnected);
+            }
+            self.update_self().await;
+            let _ = self.refresh_power_state().await;
+        }
+        Ok(self)
+    }
+
+    /// Look for this device on the network by MAC or serial number
+    async fn rediscover(&self) -> Option<Device> {
+        let macs: Vec<[u8; 6]> = [self.mac_wol, self.mac_eth, self.mac_wlan].into_iter().flatten().collect();
+        let serial = self.info.as_ref()
+            .map(|info| info.serial_number.clone())
+            .filter(|serial| !serial.is_empty());
+
+        for device in discover_devices(REDISCOVERY_TIMEOUT).await.ok()? {
+            // SSDP only advertises the WoL MAC, so fall back to asking for the serial
+            if device.mac_wol.map(|mac| macs.contains(&mac)).unwrap_or(false) {
+                return Some(device);
+            }
+            if let Some(serial) = &serial {
+                if let Ok(Ok(xml)) = tokio::time::timeout(PROBE_TIMEOUT, device.fetch("query/device-info")).await {
+                    if parse_device_info(&xml).get("serial-number") == Some(serial) {
+                        return Some(device);
+                    }
+                }
+            }
+        }
+        None
+    }
+}
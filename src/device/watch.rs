@@ -0,0 +1,209 @@
+use crate::device::{parse_active_app, parse_device_info, xml_attribute};
+use crate::{App, Device, DeviceInfo, Error, NetworkType, PowerState};
+use std::fmt;
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+// How many events can queue up before the watcher waits for them to be read
+const EVENT_BUFFER: usize = 32;
+
+/// Media player states reported by query/media-player
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub enum MediaState {
+    #[default]
+    None,       // Nothing loaded
+    Open,       // Loading
+    Buffer,     // Buffering
+    Play,       // Playing
+    Pause,      // Paused
+    Stop,       // Stopped
+    Close,      // Closing
+    Other(String),  // Anything else the device reports
+}
+
+impl From<&str> for MediaState {
+    fn from(s: &str) -> Self {
+        match s.to_ascii_lowercase().as_str() {
+            "" | "none" => MediaState::None,
+            "open" | "startup" => MediaState::Open,
+            "buffer" | "buffering" => MediaState::Buffer,
+            "play" => MediaState::Play,
+            "pause" => MediaState::Pause,
+            "stop" => MediaState::Stop,
+            "close" => MediaState::Close,
+            other => MediaState::Other(String::from(other)),
+        }
+    }
+}
+
+impl From<String> for MediaState { fn from(s: String) -> Self { MediaState::from(s.as_str()) } }
+
+impl fmt::Display for MediaState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MediaState::None => write!(f, "none"),
+            MediaState::Open => write!(f, "open"),
+            MediaState::Buffer => write!(f, "buffer"),
+            MediaState::Play => write!(f, "play"),
+            MediaState::Pause => write!(f, "pause"),
+            MediaState::Stop => write!(f, "stop"),
+            MediaState::Close => write!(f, "close"),
+            MediaState::Other(state) => write!(f, "{}", state),
+        }
+    }
+}
+
+/// A change noticed on the device
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum DeviceEvent {
+    PowerChanged { from: PowerState, to: PowerState },
+    ActiveAppChanged { from: Option<App>, to: Option<App> },
+    MediaStateChanged { from: MediaState, to: MediaState },
+    InputChanged { from: Option<String>, to: Option<String> },     // TV input, e.g. "tvinput.hdmi1"
+    NetworkChanged { from: Option<NetworkType>, to: Option<NetworkType> },
+}
+
+impl DeviceEvent {
+    /// Which kind of event this is
+    pub fn kind(&self) -> EventKind {
+        match self {
+            DeviceEvent::PowerChanged { .. } => EventKind::PowerChanged,
+            DeviceEvent::ActiveAppChanged { .. } => EventKind::ActiveAppChanged,
+            DeviceEvent::MediaStateChanged { .. } => EventKind::MediaStateChanged,
+            DeviceEvent::InputChanged { .. } => EventKind::InputChanged,
+            DeviceEvent::NetworkChanged { .. } => EventKind::NetworkChanged,
+        }
+    }
+}
+
+/// Kinds of events, one per DeviceEvent variant
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum EventKind {
+    PowerChanged,
+    ActiveAppChanged,
+    MediaStateChanged,
+    InputChanged,
+    NetworkChanged,
+}
+
+impl fmt::Display for EventKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EventKind::PowerChanged => write!(f, "power-changed"),
+            EventKind::ActiveAppChanged => write!(f, "active-app-changed"),
+            EventKind::MediaStateChanged => write!(f, "media-state-changed"),
+            EventKind::InputChanged => write!(f, "input-changed"),
+            EventKind::NetworkChanged => write!(f, "network-changed"),
+        }
+    }
+}
+
+/// Receives device events until dropped, which stops the polling
+// NOTE: ECP has no volume query, so volume changes can't be reported
+pub struct DeviceWatcher {
+    events: mpsc::Receiver<DeviceEvent>,
+    task:   JoinHandle<()>,
+}
+
+impl DeviceWatcher {
+    /// Wait for the next event, or None if the watcher stopped
+    pub async fn next(&mut self) -> Option<DeviceEvent> {
+        self.events.recv().await
+    }
+}
+
+impl Drop for DeviceWatcher {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Everything the watcher compares between polls
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Snapshot {
+    pub(crate) power:      PowerState,
+    pub(crate) app:        Option<App>,
+    pub(crate) input:      Option<String>,
+    pub(crate) media:      MediaState,
+    pub(crate) network:    Option<NetworkType>,
+    pub(crate) info:       Option<DeviceInfo>,  // Last device-info, for details nothing watches for changes (e.g. uptime)
+}
+
+impl Snapshot {
+    /// Previous state of a device that stopped answering, which counts as Off
+    pub(crate) fn unreachable(previous: Option<&Snapshot>) -> Snapshot {
+        let mut snapshot = previous.cloned().unwrap_or_default();
+        snapshot.power = PowerState::Off;
+        snapshot
+    }
+
+    /// Events for everything that changed since the previous snapshot
+    pub(crate) fn diff(&self, next: &Snapshot) -> Vec<DeviceEvent> {
+        let mut events = Vec::new();
+        if self.power != next.power {
+            events.push(DeviceEvent::PowerChanged { from: self.power.clone(), to: next.power.clone() });
+        }
+        if self.app != next.app {
+            events.push(DeviceEvent::ActiveAppChanged { from: self.app.clone(), to: next.app.clone() });
+        }
+        if self.input != next.input {
+            events.push(DeviceEvent::InputChanged { from: self.input.clone(), to: next.input.clone() });
+        }
+        if self.media != next.media {
+            events.push(DeviceEvent::MediaStateChanged { from: self.media.clone(), to: next.media.clone() });
+        }
+        if self.network != next.network {
+            events.push(DeviceEvent::NetworkChanged { from: self.network.clone(), to: next.network.clone() });
+        }
+        events
+    }
+}
+
+impl Device {
+    /// Poll the device every interval and report what changed
+    pub fn watch(&self, interval: Duration) -> DeviceWatcher {
+        let (sender, events) = mpsc::channel(EVENT_BUFFER);
+        let device = self.clone();
+
+        let task = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            let mut previous: Option<Snapshot> = None;
+            loop {
+                ticker.tick().await;
+                let next = device.snapshot(previous.as_ref()).await
+                    .unwrap_or_else(|_| Snapshot::unreachable(previous.as_ref()));
+                // Only report changes, not the initial state
+                if let Some(previous) = &previous {
+                    for event in previous.diff(&next) {
+                        if sender.send(event).await.is_err() {
+                            return;
+                        }
+                    }
+                }
+                previous = Some(next);
+            }
+        });
+
+        DeviceWatcher { events, task }
+    }
+
+    /// Query everything the watcher compares, keeping previous values for anything else that can't be read
+    // Fails if the device doesn't answer device-info at all
+    pub(crate) async fn snapshot(&self, previous: Option<&Snapshot>) -> Result<Snapshot, Error> {
+        let mut snapshot = previous.cloned().unwrap_or_default();
+
+        let info = DeviceInfo::from(parse_device_info(&self.fetch("query/device-info").await?));
+        snapshot.power = info.power_mode.clone();
+        snapshot.network = info.network_type.clone();
+        snapshot.info = Some(info);
+        if let Ok(xml) = self.fetch("query/active-app").await {
+            snapshot.app = parse_active_app(&xml);
+            snapshot.input = xml_attribute(&xml, b"app", b"id").filter(|id| id.starts_with("tvinput."));
+        }
+        if let Ok(xml) = self.fetch("query/media-player").await {
+            snapshot.media = MediaState::from(xml_attribute(&xml, b"player", b"state").unwrap_or_default());
+        }
+        Ok(snapshot)
+    }
+}
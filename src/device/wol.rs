@@ -0,0 +1,81 @@
+use wake_on_lan::MagicPacket;
+
+use crate::{Device, Error};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::net::{Ipv4Addr, SocketAddrV4};
+
+/// Where and how Wake-on-LAN magic packets are sent
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct WakeOnLanOptions {
+    pub broadcast:  Ipv4Addr,           // Destination, e.g. a directed broadcast like 192.168.20.255 for another VLAN
+    pub port:       u16,                // Destination UDP port (usually 9, sometimes 7)
+    pub source:     Option<Ipv4Addr>,   // Local address to send from
+    pub interface:  Option<String>,     // Network interface to send on, e.g. "eth0.20" (Linux only)
+    pub password:   Option<Vec<u8>>,    // SecureOn password (4 or 6 bytes), appended to the packet
+}
+
+impl Default for WakeOnLanOptions {
+    fn default() -> Self {
+        WakeOnLanOptions {
+            broadcast: Ipv4Addr::BROADCAST,
+            port: 9,
+            source: None,
+            interface: None,
+            password: None,
+        }
+    }
+}
+
+impl WakeOnLanOptions {
+    /// Build the magic packet for a MAC, with the SecureOn password if there is one
+    pub(crate) fn packet(&self, mac: &[u8; 6]) -> Result<Vec<u8>, Error> {
+        let mut bytes = MagicPacket::new(mac).magic_bytes().to_vec();
+        if let Some(password) = &self.password {
+            if password.len() != 4 && password.len() != 6 {
+                return Err(Error::WakeOnLan(String::from("SecureOn password must be 4 or 6 bytes")));
+            }
+            bytes.extend_from_slice(password);
+        }
+        Ok(bytes)
+    }
+}
+
+impl Device {
+    /// MAC address to wake the device with, falling back to the interface MACs if none was advertised
+    pub fn wake_on_lan_mac(&self) -> Option<[u8; 6]> {
+        self.mac_wol.or(self.mac_eth).or(self.mac_wlan)
+    }
+
+    /// Send a Wake-on-LAN magic packet to the device, using its WoL options
+    pub(crate) fn send_wake_on_lan(&self) -> Result<(), Error> {
+        let mac = self.wake_on_lan_mac()
+            .ok_or(Error::WakeOnLan(String::from("Device is off and has no known MAC address")))?;
+        let packet = self.wol.packet(&mac)?;
+
+        let send = || -> std::io::Result<()> {
+            let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+            if let Some(interface) = &self.wol.interface {
+                bind_to_interface(&socket, interface)?;
+            }
+            socket.bind(&SocketAddrV4::new(self.wol.source.unwrap_or(Ipv4Addr::UNSPECIFIED), 0).into())?;
+            socket.set_broadcast(true)?;
+            socket.send_to(&packet, &SocketAddrV4::new(self.wol.broadcast, self.wol.port).into())?;
+            Ok(())
+        };
+        send().map_err(|e| Error::WakeOnLan(format!("Unable to send Wake-on-LAN: {}", e)))
+    }
+}
+
+/// Send only through the named interface (SO_BINDTODEVICE)
+// NOTE: Kernels before 5.7 only allow this with CAP_NET_RAW
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn bind_to_interface(socket: &Socket, interface: &str) -> std::io::Result<()> {
+    socket.bind_device(Some(interface.as_bytes()))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn bind_to_interface(_socket: &Socket, _interface: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "binding to an interface is only supported on Linux, set a source address instead"))
+}
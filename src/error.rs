@@ -0,0 +1,44 @@
+use crate::Button;
+use std::fmt;
+
+/// Errors returned by device operations
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Error {
+    NotConnected,           // No open ECP-2 connection
+    NoResponse,             // The device never answered the request
+    Timeout,                // The device didn't answer in time
+    EmptyResponse,          // The device answered, but without content
+    Unsupported(Button),    // The device doesn't support this button
+    InvalidRequest(String), // The request was rejected before being sent
+    Unreachable(String),    // The device refused or couldn't be reached for an ECP (HTTP) request
+    Http(String),           // An ECP (HTTP) request failed
+    Discovery(String),      // SSDP discovery couldn't be run (e.g. the UDP socket couldn't be opened)
+    WakeOnLan(String),      // A Wake-on-LAN packet couldn't be sent
+    Parse(String),          // A device response couldn't be parsed
+    Mqtt(String),           // The MQTT broker couldn't be reached or rejected a request
+    Config(String),         // A config file or profile is missing or invalid
+    Key(String),            // An ECP-2 key couldn't be loaded
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotConnected => write!(f, "Not connected to device"),
+            Error::NoResponse => write!(f, "No response received"),
+            Error::Timeout => write!(f, "Timed out waiting for device"),
+            Error::EmptyResponse => write!(f, "Response had no content"),
+            Error::Unsupported(button) => write!(f, "Device doesn't support the {} button", button),
+            Error::InvalidRequest(message) => write!(f, "Invalid request: {}", message),
+            Error::Unreachable(message) => write!(f, "Device unreachable: {}", message),
+            Error::Http(message) => write!(f, "HTTP request failed: {}", message),
+            Error::Discovery(message) => write!(f, "SSDP discovery failed: {}", message),
+            Error::WakeOnLan(message) => write!(f, "Wake-on-LAN failed: {}", message),
+            Error::Parse(message) => write!(f, "Unable to parse response: {}", message),
+            Error::Mqtt(message) => write!(f, "MQTT failed: {}", message),
+            Error::Config(message) => write!(f, "Invalid config: {}", message),
+            Error::Key(message) => write!(f, "Unable to load key: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,186 @@
+use crate::{Button, DeviceHandle, Error};
+use std::future::Future;
+use std::time::Duration;
+use tokio::task::JoinSet;
+
+/// Several devices controlled together, e.g. every TV in a room
+///
+/// Commands go to all members concurrently and report a result per member, so one member being
+/// offline doesn't stop the others. In lock-step mode, sequences (button presses, typed text) only
+/// move on to the next key once every member has received the current one.
+#[derive(Clone)]
+pub struct DeviceGroup {
+    members:        Vec<(String, DeviceHandle)>,
+    pub lock_step:  bool,       // Keep members in step during sequences
+    pub delay:      Duration,   // Pause between keys in a sequence
+}
+
+/// Per-member results of a group command, in the order members were added
+#[derive(Clone, Debug)]
+pub struct GroupResults<T> {
+    pub results: Vec<(String, Result<T, Error>)>,
+}
+
+impl<T> GroupResults<T> {
+    /// Whether every member succeeded
+    pub fn all_ok(&self) -> bool {
+        self.results.iter().all(|(_, result)| result.is_ok())
+    }
+
+    /// Result for one member
+    pub fn get(&self, name: &str) -> Option<&Result<T, Error>> {
+        self.results.iter().find(|(n, _)| n == name).map(|(_, result)| result)
+    }
+
+    /// Members that failed, and why
+    pub fn failures(&self) -> impl Iterator<Item = (&str, &Error)> {
+        self.results.iter().filter_map(|(name, result)| result.as_ref().err().map(|e| (name.as_str(), e)))
+    }
+}
+
+impl Default for DeviceGroup {
+    fn default() -> Self {
+        DeviceGroup { members: Vec::new(), lock_step: false, delay: Duration::from_millis(100) }
+    }
+}
+
+impl DeviceGroup {
+    pub fn new() -> DeviceGroup {
+        DeviceGroup::default()
+    }
+
+    /// Add a member under a name, e.g. a Device or a DeviceHandle shared with something else
+    pub fn add(&mut self, name: &str, member: impl Into<DeviceHandle>) -> &mut DeviceGroup {
+        self.members.push((String::from(name), member.into()));
+        self
+    }
+
+    /// Remove a member by name, returning its handle
+    pub fn remove(&mut self, name: &str) -> Option<DeviceHandle> {
+        let index = self.members.iter().position(|(n, _)| n == name)?;
+        Some(self.members.remove(index).1)
+    }
+
+    /// Member names, in the order they were added
+    pub fn members(&self) -> Vec<&str> {
+        self.members.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    /// Press a button on every member
+    pub async fn press_button(&self, button: Button) -> GroupResults<()> {
+        self.each(move |handle| {
+            let button = button.clone();
+            async move { handle.press_button(button).await }
+        }).await
+    }
+
+    /// Press a sequence of buttons on every member, in lock-step if enabled
+    pub async fn press_buttons(&self, buttons: Vec<Button>) -> GroupResults<()> {
+        if self.lock_step {
+            return self.in_lock_step(buttons).await;
+        }
+        let delay = self.delay;
+        self.each(move |handle| {
+            let buttons = buttons.clone();
+            async move {
+                for (i, button) in buttons.into_iter().enumerate() {
+                    if i > 0 {
+                        tokio::time::sleep(delay).await;
+                    }
+                    handle.press_button(button).await?;
+                }
+                Ok(())
+            }
+        }).await
+    }
+
+    /// Type text into every member's on-screen keyboard, in lock-step if enabled
+    pub async fn type_text(&self, text: &str) -> GroupResults<()> {
+        self.press_buttons(text.chars().map(Button::from).collect()).await
+    }
+
+    /// Launch an app on every member, by id or name
+    // Resolved per member, since not every member may have the app installed
+    pub async fn launch_app(&self, app: &str) -> GroupResults<()> {
+        let app = String::from(app);
+        self.each(move |handle| {
+            let app = app.clone();
+            async move { handle.launch_app(&app).await.map(|_| ()) }
+        }).await
+    }
+
+    /// Turn every member's screen on, reporting whether each came on
+    pub async fn power_on(&self) -> GroupResults<bool> {
+        self.each(|handle| async move { handle.power_on().await }).await
+    }
+
+    /// Turn every member's screen off, reporting whether each went off
+    pub async fn power_off(&self) -> GroupResults<bool> {
+        self.each(|handle| async move { handle.power_off().await }).await
+    }
+
+    /// Send each button to every member and wait for all of them before the next
+    // A member that fails drops out of the rest of the sequence, so an offline TV doesn't hold up every key
+    async fn in_lock_step(&self, buttons: Vec<Button>) -> GroupResults<()> {
+        let mut results: Vec<Option<Result<(), Error>>> = vec![None; self.members.len()];
+        for (step, button) in buttons.into_iter().enumerate() {
+            let active: Vec<usize> = (0..self.members.len()).filter(|&i| results[i].is_none()).collect();
+            if active.is_empty() {
+                break;
+            }
+            if step > 0 {
+                tokio::time::sleep(self.delay).await;
+            }
+            let outcomes = fan_out(active.iter().map(|&i| {
+                let (handle, button) = (self.members[i].1.clone(), button.clone());
+                async move { handle.press_button(button).await }
+            })).await;
+            for (&i, outcome) in active.iter().zip(outcomes) {
+                if outcome.is_err() {
+                    results[i] = Some(outcome);
+                }
+            }
+        }
+        GroupResults {
+            results: self.members.iter().zip(results)
+                .map(|((name, _), result)| (name.clone(), result.unwrap_or(Ok(()))))
+                .collect(),
+        }
+    }
+
+    /// Run an operation on every member concurrently
+    async fn each<T, F, Fut>(&self, op: F) -> GroupResults<T>
+    where
+        T: Send + 'static,
+        F: Fn(DeviceHandle) -> Fut,
+        Fut: Future<Output = Result<T, Error>> + Send + 'static,
+    {
+        let outcomes = fan_out(self.members.iter().map(|(_, handle)| op(handle.clone()))).await;
+        GroupResults {
+            results: self.members.iter().map(|(name, _)| name.clone()).zip(outcomes).collect(),
+        }
+    }
+}
+
+/// Run futures as concurrent tasks, returning their outputs in the original order
+async fn fan_out<T, Fut>(futures: impl Iterator<Item = Fut>) -> Vec<T>
+where
+    T: Send + 'static,
+    Fut: Future<Output = T> + Send + 'static,
+{
+    let mut tasks = JoinSet::new();
+    let mut count = 0;
+    for (i, future) in futures.enumerate() {
+        tasks.spawn(async move { (i, future.await) });
+        count += 1;
+    }
+
+    let mut outputs: Vec<Option<T>> = (0..count).map(|_| None).collect();
+    while let Some(joined) = tasks.join_next().await {
+        match joined {
+            Ok((i, output)) => outputs[i] = Some(output),
+            Err(e) => std::panic::resume_unwind(e.into_panic()),
+        }
+    }
+    outputs.into_iter().map(|o| o.expect("every task finished")).collect()
+}
@@ -0,0 +1,260 @@
+//! Where ECP-2 keys come from: the environment, a private file, or a passphrase-encrypted file
+
+use crate::Error;
+use std::path::{Path, PathBuf};
+
+// Start of an encrypted key file, followed by the salt, nonce and ciphertext
+const ENCRYPTED_MAGIC: &[u8] = b"KORUKEY1";
+#[cfg(feature = "encryption")]
+const SALT_LEN: usize = 16;
+#[cfg(feature = "encryption")]
+const NONCE_LEN: usize = 12;
+
+// Shown in place of keys and passphrases in Debug output
+pub(crate) const REDACTED: &str = "<redacted>";
+
+/// Supplies an ECP-2 key when a device needs one
+pub trait KeyProvider: Send + Sync {
+    fn key(&self) -> Result<Vec<u8>, Error>;
+}
+
+/// Key given directly, e.g. on the command line or in a config file
+#[derive(Clone)]
+pub struct InlineKey {
+    key: Vec<u8>,
+}
+
+impl InlineKey {
+    pub fn new(key: impl Into<Vec<u8>>) -> InlineKey {
+        InlineKey { key: key.into() }
+    }
+}
+
+impl std::fmt::Debug for InlineKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("InlineKey").field("key", &REDACTED).finish()
+    }
+}
+
+impl KeyProvider for InlineKey {
+    fn key(&self) -> Result<Vec<u8>, Error> {
+        match self.key.is_empty() {
+            true => Err(Error::Key(String::from("The key is empty"))),
+            false => Ok(self.key.clone()),
+        }
+    }
+}
+
+/// Key from an environment variable
+#[derive(Clone, Debug)]
+pub struct EnvKey {
+    var: String,
+}
+
+impl EnvKey {
+    pub fn new(var: &str) -> EnvKey {
+        EnvKey { var: String::from(var) }
+    }
+}
+
+impl Default for EnvKey {
+    /// KORU_ECP2_KEY, the same variable the command-line tools read
+    fn default() -> Self {
+        EnvKey::new("KORU_ECP2_KEY")
+    }
+}
+
+impl KeyProvider for EnvKey {
+    fn key(&self) -> Result<Vec<u8>, Error> {
+        match std::env::var(&self.var) {
+            Ok(key) if !key.is_empty() => Ok(key.into_bytes()),
+            _ => Err(Error::Key(format!("{} isn't set", self.var))),
+        }
+    }
+}
+
+/// Key stored as plain text in a file only its owner can read
+// Trailing whitespace (e.g. the newline an editor adds) isn't part of the key
+#[derive(Clone, Debug)]
+pub struct FileKey {
+    path: PathBuf,
+}
+
+impl FileKey {
+    pub fn new(path: impl AsRef<Path>) -> FileKey {
+        FileKey { path: path.as_ref().to_path_buf() }
+    }
+}
+
+impl KeyProvider for FileKey {
+    fn key(&self) -> Result<Vec<u8>, Error> {
+        check_permissions(&self.path)?;
+        let contents = read(&self.path)?;
+        if contents.starts_with(ENCRYPTED_MAGIC) {
+            return Err(Error::Key(format!("{} is encrypted, it needs a passphrase", self.path.display())));
+        }
+        let key = contents.trim_ascii_end().to_vec();
+        if key.is_empty() {
+            return Err(Error::Key(format!("{} is empty", self.path.display())));
+        }
+        Ok(key)
+    }
+}
+
+/// Key encrypted at rest with a passphrase (Argon2id key derivation, ChaCha20-Poly1305)
+#[cfg(feature = "encryption")]
+#[derive(Clone)]
+pub struct EncryptedFileKey {
+    path:       PathBuf,
+    passphrase: String,
+}
+
+#[cfg(feature = "encryption")]
+impl EncryptedFileKey {
+    pub fn new(path: impl AsRef<Path>, passphrase: &str) -> EncryptedFileKey {
+        EncryptedFileKey { path: path.as_ref().to_path_buf(), passphrase: String::from(passphrase) }
+    }
+
+    /// Encrypt a key with a passphrase and write it to a new file only its owner can read
+    pub fn create(path: impl AsRef<Path>, key: &[u8], passphrase: &str) -> Result<EncryptedFileKey, Error> {
+        use chacha20poly1305::aead::rand_core::RngCore;
+        use chacha20poly1305::aead::{Aead, AeadCore, OsRng, Payload};
+
+        let mut salt = [0; SALT_LEN];
+        OsRng.fill_bytes(&mut salt);
+        let nonce = chacha20poly1305::ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = cipher(passphrase, &salt)?
+            .encrypt(&nonce, Payload { msg: key, aad: ENCRYPTED_MAGIC })
+            .map_err(|_| Error::Key(String::from("Encryption failed")))?;
+
+        let contents = [ENCRYPTED_MAGIC, &salt, &nonce, &ciphertext].concat();
+        write_private(path.as_ref(), &contents)?;
+        Ok(EncryptedFileKey::new(path, passphrase))
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl KeyProvider for EncryptedFileKey {
+    fn key(&self) -> Result<Vec<u8>, Error> {
+        use chacha20poly1305::aead::{Aead, Payload};
+        use chacha20poly1305::Nonce;
+
+        let contents = read(&self.path)?;
+        let rest = contents.strip_prefix(ENCRYPTED_MAGIC)
+            .filter(|rest| rest.len() > SALT_LEN + NONCE_LEN)
+            .ok_or_else(|| Error::Key(format!("{} isn't an encrypted key file", self.path.display())))?;
+        let (salt, rest) = rest.split_at(SALT_LEN);
+        let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+        cipher(&self.passphrase, salt)?
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: ciphertext, aad: ENCRYPTED_MAGIC })
+            .map_err(|_| Error::Key(format!("Wrong passphrase for {}, or the file was modified", self.path.display())))
+    }
+}
+
+#[cfg(feature = "encryption")]
+impl std::fmt::Debug for EncryptedFileKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptedFileKey").field("path", &self.path).field("passphrase", &REDACTED).finish()
+    }
+}
+
+/// Key file in either format (like load_key_file), read and decrypted whenever the key is needed
+#[derive(Clone)]
+pub struct AnyFileKey {
+    path:       PathBuf,
+    passphrase: Option<String>,
+}
+
+impl AnyFileKey {
+    pub fn new(path: impl AsRef<Path>, passphrase: Option<&str>) -> AnyFileKey {
+        AnyFileKey { path: path.as_ref().to_path_buf(), passphrase: passphrase.map(String::from) }
+    }
+}
+
+impl std::fmt::Debug for AnyFileKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let passphrase = self.passphrase.as_ref().map(|_| REDACTED);
+        f.debug_struct("AnyFileKey").field("path", &self.path).field("passphrase", &passphrase).finish()
+    }
+}
+
+impl KeyProvider for AnyFileKey {
+    fn key(&self) -> Result<Vec<u8>, Error> {
+        load_key_file(&self.path, self.passphrase.as_deref())
+    }
+}
+
+/// Whether a file holds an encrypted key (as opposed to a plain one)
+pub fn is_encrypted_key_file(path: impl AsRef<Path>) -> bool {
+    read(path.as_ref()).map(|c| c.starts_with(ENCRYPTED_MAGIC)).unwrap_or(false)
+}
+
+/// Load a key file, decrypting it with the passphrase if it's encrypted
+pub fn load_key_file(path: impl AsRef<Path>, passphrase: Option<&str>) -> Result<Vec<u8>, Error> {
+    let path = path.as_ref();
+    if !is_encrypted_key_file(path) {
+        return FileKey::new(path).key();
+    }
+    match passphrase {
+        #[cfg(feature = "encryption")]
+        Some(passphrase) => EncryptedFileKey::new(path, passphrase).key(),
+        #[cfg(not(feature = "encryption"))]
+        Some(_) => Err(Error::Key(format!("{} is encrypted, but koru was built without the encryption feature", path.display()))),
+        None => Err(Error::Key(format!("{} is encrypted, it needs a passphrase", path.display()))),
+    }
+}
+
+/// Cipher keyed from a passphrase and salt
+#[cfg(feature = "encryption")]
+fn cipher(passphrase: &str, salt: &[u8]) -> Result<chacha20poly1305::ChaCha20Poly1305, Error> {
+    use chacha20poly1305::KeyInit;
+
+    let mut key = [0; 32];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| Error::Key(format!("Unable to derive key from passphrase: {}", e)))?;
+    Ok(chacha20poly1305::ChaCha20Poly1305::new(&key.into()))
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, Error> {
+    std::fs::read(path).map_err(|e| Error::Key(format!("Unable to read {}: {}", path.display(), e)))
+}
+
+/// Refuse key files that anyone but their owner can read or write
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Result<(), Error> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = std::fs::metadata(path)
+        .map_err(|e| Error::Key(format!("Unable to read {}: {}", path.display(), e)))?
+        .permissions()
+        .mode();
+    if mode & 0o077 != 0 {
+        return Err(Error::Key(format!(
+            "{} is accessible by other users (mode {:o}), restrict it with chmod 600", path.display(), mode & 0o777,
+        )));
+    }
+    Ok(())
+}
+
+// NOTE: Windows ACLs aren't checked
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Result<(), Error> {
+    Ok(())
+}
+
+/// Write a file that only its owner can read, failing if it already exists
+#[cfg(feature = "encryption")]
+fn write_private(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    use std::io::Write;
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)
+        .and_then(|mut file| file.write_all(contents))
+        .map_err(|e| Error::Key(format!("Unable to write {}: {}", path.display(), e)))
+}
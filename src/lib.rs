@@ -3,13 +3,38 @@ mod app;
 mod remote;
 mod device;
 mod ssdp;
+#[cfg(feature = "serde")]
 mod config;
+mod search;
+mod error;
+mod group;
+mod keys;
+mod registry;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "metrics")]
+mod metrics;
+#[cfg(feature = "serde")]
+mod serialize;
 
 // Re-export higher-level stuff
 pub use crate::app::*;
 pub use crate::remote::*;
 pub use crate::device::*;
+pub use crate::search::*;
+#[cfg(feature = "serde")]
+pub use crate::config::{DeviceProfile, KoruConfig, WolProfile};
+pub use crate::error::Error;
+pub use crate::group::{DeviceGroup, GroupResults};
+pub use crate::keys::{is_encrypted_key_file, load_key_file, AnyFileKey, EnvKey, FileKey, InlineKey, KeyProvider};
+#[cfg(feature = "encryption")]
+pub use crate::keys::EncryptedFileKey;
+pub use crate::registry::{DeviceUpdate, Registry, RegistryChanges, RegistryEntry, RegistryWatcher};
 pub use crate::ssdp::discover_devices;
+#[cfg(feature = "mqtt")]
+pub use crate::mqtt::{BridgeOptions, MqttBridge};
+#[cfg(feature = "metrics")]
+pub use crate::metrics::Metrics;
 
 #[cfg(test)]
 mod tests {
@@ -89,13 +114,742 @@ mod tests {
         }
     }
 
+    #[test]
+    fn build_search_query_string() {
+        let query = SearchQuery {
+            keyword: String::from("the office"),
+            search_type: Some(SearchType::TvShow),
+            season: Some(3),
+            provider_ids: vec![12, 13],
+            launch: true,
+            match_any: false,
+        };
+        assert_eq!(
+            query.to_query_string(),
+            "keyword=the%20office&type=tv-show&season=3&provider-id=12,13&launch=true"
+        );
+        assert_eq!(SearchQuery::new("x").to_query_string(), "keyword=x");
+        assert_eq!("TV".parse::<SearchType>(), Ok(SearchType::TvShow));
+        assert_eq!("movie".parse::<SearchType>(), Ok(SearchType::Movie));
+        assert!("documentary".parse::<SearchType>().is_err());
+    }
+
+    #[test]
+    fn parse_buttons() {
+        // Every named button survives a round trip through its ECP key name
+        for button in Button::ALL.iter() {
+            assert_eq!(button.to_string().parse::<Button>(), Ok(button.clone()));
+        }
+        // Literals round trip too, including ones that need percent-encoding
+        for character in ['a', '-', '%', ' ', '&', 'é', '🦀'] {
+            let button = Button::Literal { character };
+            assert_eq!(button.to_string().parse::<Button>(), Ok(button));
+        }
+        assert_eq!(Button::from('&').to_string(), "Lit_-%26");
+        assert_eq!(Button::from('é').to_string(), "Lit_-%C3%A9");
+        assert_eq!("Lit_x".parse::<Button>(), Ok(Button::Literal { character: 'x' }));
+        assert_eq!("volup".parse::<Button>(), Ok(Button::VolumeUp));
+        assert_eq!("inputhdmi1".parse::<Button>(), Ok(Button::InputHdmi1));
+        assert!("NotAButton".parse::<Button>().is_err());
+        assert!("Lit_ab".parse::<Button>().is_err());
+    }
+
+    #[test]
+    fn parse_macro() {
+        let script: Macro = "# Open the news\nHome\nwait 1.5s\n\nDown x3\ntype \"news & weather\"\nlaunch Netflix\nwait-for-app 12 timeout 500ms"
+            .parse()
+            .unwrap();
+        assert_eq!(script.steps, vec![
+            (2, MacroStep::Press { button: Button::Home, count: 1 }),
+            (3, MacroStep::Wait(Duration::from_millis(1500))),
+            (5, MacroStep::Press { button: Button::Down, count: 3 }),
+            (6, MacroStep::Type(String::from("news & weather"))),
+            (7, MacroStep::Launch(AppRef::Name(String::from("Netflix")))),
+            (8, MacroStep::WaitForApp { app: AppRef::Id(12), timeout: Duration::from_millis(500) }),
+        ]);
+
+        let error = "Home\nwait forever".parse::<Macro>().unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_eq!("Home\nSelect x\n".parse::<Macro>().unwrap_err().line, 2);
+        assert_eq!("Hom".parse::<Macro>().unwrap_err().line, 1);
+    }
+
+    #[test]
+    fn parse_active_app() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8" ?>
+<active-app>
+    <app id="12" type="appl" version="4.1.218">Netflix</app>
+    <screensaver id="55545" type="ssvr" version="2.0.1">Default screensaver</screensaver>
+</active-app>"#;
+        let app = device::parse_active_app(xml).unwrap();
+        assert_eq!((app.id, app.name.as_str(), app.apptype.as_str()), (12, "Netflix", "appl"));
+        assert_eq!(device::parse_active_app("<active-app><app>Roku</app></active-app>"), None);
+
+        let input = r#"<active-app><app id="tvinput.hdmi1" type="tvin" version="1.0.0">HDMI 1</app></active-app>"#;
+        assert_eq!(device::xml_attribute(input, b"app", b"id").as_deref(), Some("tvinput.hdmi1"));
+        let player = r#"<player error="false" state="pause"><plugin id="12" bandwidth="1" name="Netflix"/></player>"#;
+        assert_eq!(MediaState::from(device::xml_attribute(player, b"player", b"state").unwrap()), MediaState::Pause);
+    }
+
+    #[test]
+    fn parse_installed_apps() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8" ?>
+<apps>
+    <app id="12" type="appl" version="4.1.218">Netflix</app>
+    <app id="tvinput.hdmi1" type="tvin" version="1.0.0">HDMI 1</app>
+</apps>"#;
+        assert!(matches!(device::parse_installed_apps(xml), Err(Error::Parse(_))));
+        let apps = device::parse_installed_apps(r#"<apps><app id="12" type="appl" version="4.1.218">Netflix</app></apps>"#).unwrap();
+        assert_eq!((apps[0].id, apps[0].name.as_str()), (12, "Netflix"));
+        assert!(matches!(device::parse_installed_apps(r#"<apps><app id="12">Netflix</app></apps>"#), Err(Error::Parse(_))));
+    }
+
+    #[test]
+    fn buttons_follow_device_info() {
+        let mut raw = std::collections::HashMap::new();
+        raw.insert(String::from("is-tv"), String::from("false"));
+        raw.insert(String::from("supports-find-remote"), String::from("true"));
+        let player = DeviceInfo::from(raw.clone());
+        assert!(player.supports_button(&Button::FindRemote));
+        assert!(player.supports_button(&Button::Home));
+        assert!(!player.supports_button(&Button::VolumeUp));
+        assert!(!player.supported_buttons().contains(&Button::InputHdmi1));
+        // Nothing in device-info says whether the numpad or Guide work, so they aren't refused
+        assert!(player.supports_button(&Button::Numpad1));
+        assert!(player.supports_button(&Button::Guide));
+
+        raw.insert(String::from("is-tv"), String::from("true"));
+        let tv = DeviceInfo::from(raw);
+        assert!(tv.supports_button(&Button::VolumeUp));
+        assert!(tv.supported_buttons().contains(&Button::InputHdmi1));
+        assert_eq!(tv.power_mode, PowerState::Unknown);
+    }
+
+    #[tokio::test]
+    async fn hold_button_over_http() {
+        let (mut device, requests) = fake_ecp(vec![]).await;
+        let mut raw = std::collections::HashMap::new();
+        raw.insert(String::from("is-tv"), String::from("false"));
+        device.info = Some(DeviceInfo::from(raw));
+
+        // Key-down/up don't need an ECP-2 connection, but still respect capabilities
+        assert_eq!(device.key_down(Button::VolumeUp).await, Err(Error::Unsupported(Button::VolumeUp)));
+        device.hold_button(Button::Up, Duration::from_millis(10)).await.unwrap();
+        assert_eq!(*requests.lock().unwrap(), vec!["POST /keydown/Up", "POST /keyup/Up"]);
+    }
+
+    #[tokio::test]
+    async fn send_over_http_transport() {
+        let apps = r#"<apps><app id="12" type="appl" version="1.0">Netflix</app></apps>"#;
+        let (mut device, requests) = fake_ecp(vec![("GET /query/device-info", "<device-info><is-tv>false</is-tv></device-info>"), ("GET /query/apps", apps)]).await;
+        device.transport = Transport::Http;
+
+        // Plain ECP never needs an ECP-2 connection
+        device.press_button(Button::Home).await.unwrap();
+        assert_eq!(device.press_button(Button::VolumeUp).await, Err(Error::Unsupported(Button::VolumeUp)));
+        assert_eq!(device.get_installed_apps().await.unwrap()[0].id, 12);
+        device.launch_app_by_id(12).await.unwrap();
+        assert_eq!(*requests.lock().unwrap(), vec!["GET /query/device-info", "POST /keypress/Home", "GET /query/apps", "POST /launch/12"]);
+
+        // Macros launch by name the same way launch_app does, and stop at an app that isn't installed
+        requests.lock().unwrap().clear();
+        let error = device.run_macro("launch netflix\nlaunch Hulu").await.unwrap_err();
+        assert_eq!(error.line, 2);
+        assert!(error.message.contains("Hulu"));
+        assert_eq!(*requests.lock().unwrap(), vec!["GET /query/apps", "POST /launch/12", "GET /query/apps"]);
+    }
+
+    #[tokio::test]
+    async fn refresh_power_over_http() {
+        let info = "<device-info><power-mode>DisplayOff</power-mode><is-tv>true</is-tv></device-info>";
+        let (mut device, _) = fake_ecp(vec![("GET /query/device-info", info)]).await;
+        // No ECP-2 connection needed to read the power state
+        assert_eq!(device.refresh_power_state().await, Ok(PowerState::DisplayOff));
+
+        // Nothing listening is a device that needs waking
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut device = Device::new("127.0.0.1", port as i32);
+        assert_eq!(device.refresh_power_state().await, Ok(PowerState::Off));
+
+        // A device that answers with an error status is still on, so the error comes back
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut device = Device::new("127.0.0.1", listener.local_addr().unwrap().port() as i32);
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let _ = socket.read(&mut [0; 1024]).await;
+            let _ = socket.write_all(b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+        });
+        assert!(matches!(device.refresh_power_state().await, Err(Error::Http(_))));
+    }
+
+    /// Stand-in for a device's ECP (HTTP) endpoints, answering from `routes` and recording each request
+    async fn fake_ecp(routes: Vec<(&'static str, &'static str)>) -> (Device, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        slow_ecp(routes, "", Duration::ZERO).await
+    }
+
+    /// Same as fake_ecp, but takes `delay` to answer the `slow` route
+    async fn slow_ecp(routes: Vec<(&'static str, &'static str)>, slow: &'static str, delay: Duration) -> (Device, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let requests = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut socket, _)) = listener.accept().await {
+                let (routes, seen) = (routes.clone(), seen.clone());
+                tokio::spawn(async move {
+                    let mut request = Vec::new();
+                    let mut buffer = [0; 1024];
+                    while !request.windows(4).any(|w| w == b"\r\n\r\n") {
+                        match socket.read(&mut buffer).await {
+                            Ok(0) | Err(_) => return,
+                            Ok(n) => request.extend_from_slice(&buffer[..n]),
+                        }
+                    }
+                    // e.g. "POST /keypress/Home" from "POST /keypress/Home HTTP/1.1"
+                    let line = String::from_utf8_lossy(&request).lines().next().unwrap_or_default().to_string();
+                    let target = line.rsplit_once(' ').map(|(t, _)| t.to_string()).unwrap_or_default();
+                    let body = routes.iter().find(|(r, _)| *r == target).map(|(_, b)| *b).unwrap_or_default();
+                    if target == slow {
+                        tokio::time::sleep(delay).await;
+                    }
+                    seen.lock().unwrap().push(target);
+                    let response = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+                    let _ = socket.write_all(response.as_bytes()).await;
+                });
+            }
+        });
+        (Device::new("127.0.0.1", port as i32), requests)
+    }
+
+    #[test]
+    fn parse_power_modes() {
+        for (mode, state) in [("PowerOn", PowerState::On), ("DisplayOff", PowerState::DisplayOff), ("Ready", PowerState::Ready), ("Headless", PowerState::Headless)] {
+            let mut raw = std::collections::HashMap::new();
+            raw.insert(String::from("power-mode"), String::from(mode));
+            assert_eq!(DeviceInfo::from(raw).power_mode, state);
+        }
+        assert!(PowerState::Ready.is_off());
+        assert!(!PowerState::On.is_off());
+    }
+
+    #[test]
+    fn parse_addresses() {
+        let ipv4 = std::net::Ipv4Addr::new(10, 0, 0, 2);
+        assert_eq!(parse_address("10.0.0.2"), Some((ipv4, 8060)));
+        assert_eq!(parse_address("10.0.0.2:9000"), Some((ipv4, 9000)));
+        assert_eq!(parse_address("10.0.0.2:port"), None);
+        assert_eq!(parse_address("living-room"), None);
+    }
+
+    #[test]
+    fn build_wake_on_lan_packet() {
+        let mac = [0x0f, 0x1e, 0x2d, 0x3c, 0x4b, 0x5a];
+        let mut options = WakeOnLanOptions::default();
+        let packet = options.packet(&mac).unwrap();
+        assert_eq!(packet.len(), 102);
+        assert_eq!(&packet[..6], &[0xff; 6]);
+        assert_eq!(&packet[96..], &mac);
+
+        options.password = Some(vec![1, 2, 3, 4]);
+        assert_eq!(&options.packet(&mac).unwrap()[102..], &[1, 2, 3, 4]);
+        options.password = Some(vec![1, 2, 3]);
+        assert!(options.packet(&mac).is_err());
+
+        // Falls back to the interface MACs, and agrees with supports_wake_on_lan
+        let mut device = Device::new("127.0.0.1", 8060);
+        assert!(!device.supports_wake_on_lan());
+        device.mac_eth = Some(mac);
+        assert!(device.supports_wake_on_lan());
+        assert_eq!(device.wake_on_lan_mac(), Some(mac));
+        device.wol.broadcast = std::net::Ipv4Addr::LOCALHOST;
+        device.wol.interface = Some(String::from("koru-missing0"));
+        assert!(matches!(device.send_wake_on_lan(), Err(Error::WakeOnLan(_))));
+    }
+
+    #[tokio::test]
+    async fn share_device_handle() {
+        let info = "<device-info><power-mode>DisplayOff</power-mode><is-tv>true</is-tv></device-info>";
+        let active = r#"<active-app><app id="12" type="appl" version="4.1.218">Netflix</app></active-app>"#;
+        let (device, _) = fake_ecp(vec![("GET /query/device-info", info), ("GET /query/active-app", active)]).await;
+        let handle = device.share();
+        let shared = handle.clone();
+
+        // Clones don't wait on each other
+        let (a, b) = tokio::join!(handle.get_active_app(), shared.get_active_app());
+        assert_eq!(a.unwrap().map(|app| app.id), Some(12));
+        assert_eq!(b.unwrap().map(|app| app.id), Some(12));
+
+        // What a call learns about the device is kept for every clone
+        assert_eq!(shared.device().power_state, PowerState::Unknown);
+        assert_eq!(handle.power_off().await, Ok(true));
+        assert_eq!(shared.device().power_state, PowerState::DisplayOff);
+    }
+
+    #[tokio::test]
+    async fn keep_concurrent_handle_updates() {
+        let info = "<device-info><power-mode>DisplayOff</power-mode><is-tv>true</is-tv></device-info>";
+        let (mut device, _) = slow_ecp(vec![("GET /query/device-info", info)], "POST /keypress/Home", Duration::from_millis(300)).await;
+        device.transport = Transport::Http;
+        device.info = Some(DeviceInfo::default());
+        let handle = device.share();
+
+        // A press that started before power_off and finished after it doesn't restore the old power state
+        let pressing = tokio::spawn({
+            let handle = handle.clone();
+            async move { handle.press_button(Button::Home).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(handle.power_off().await, Ok(true));
+        assert_eq!(pressing.await.unwrap(), Ok(()));
+        assert_eq!(handle.device().power_state, PowerState::DisplayOff);
+    }
+
+    /// Stand-in for an ECP-2 connection, answering with its own id (or never, if it's stuck) and logging every request
+    struct FakeLink {
+        id:     u32,
+        stuck:  bool,
+        log:    std::sync::Arc<std::sync::Mutex<Vec<&'static str>>>,
+    }
+
+    impl crate::device::Link for FakeLink {
+        type Request = &'static str;
+        type Response = (u32, &'static str);
+
+        async fn send(&mut self, request: &'static str) -> Option<(u32, &'static str)> {
+            self.log.lock().unwrap().push(request);
+            if self.stuck {
+                std::future::pending::<()>().await;
+            }
+            Some((self.id, request))
+        }
+
+        fn ping() -> &'static str {
+            "ping"
+        }
+    }
+
+    #[tokio::test]
+    async fn share_connection_task() {
+        use crate::device::{ConnectionHealth, Opener, Session};
+        let log = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let open: Opener<FakeLink> = Box::new(|| Box::pin(async { None }));
+        let link = FakeLink { id: 0, stuck: false, log: log.clone() };
+        let session = Session::start(link, open, "127.0.0.1", ReconnectOptions::default(), Duration::from_secs(1), ConnectionHealth::default());
+        let shared = session.clone();
+
+        // Clones send over the one connection, in turn, and each gets its own response back
+        let (a, b) = tokio::join!(
+            tokio::spawn({
+                let session = session.clone();
+                async move { session.request("a", Duration::from_secs(1)).await }
+            }),
+            tokio::spawn({
+                let shared = shared.clone();
+                async move { shared.request("b", Duration::from_secs(1)).await }
+            }),
+        );
+        assert_eq!((a.unwrap(), b.unwrap()), (Ok((0, "a")), Ok((0, "b"))));
+        assert_eq!(log.lock().unwrap().len(), 2);
+
+        // The connection goes away with the last clone
+        drop((session, shared));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(std::sync::Arc::strong_count(&log), 1);
+    }
+
+    #[tokio::test]
+    async fn reconnect_after_timeout() {
+        use crate::device::{ConnectionHealth, Opener, Session};
+        use std::sync::atomic::{AtomicU32, Ordering};
+        let log = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let opened = std::sync::Arc::new(AtomicU32::new(0));
+        let (count, links) = (opened.clone(), log.clone());
+        let open: Opener<FakeLink> = Box::new(move || {
+            let link = FakeLink { id: count.fetch_add(1, Ordering::SeqCst) + 1, stuck: false, log: links.clone() };
+            Box::pin(async move { Some(link) })
+        });
+        let health = ConnectionHealth::default();
+        let stuck = FakeLink { id: 0, stuck: true, log: log.clone() };
+        let session = Session::start(stuck, open, "127.0.0.1", ReconnectOptions::default(), Duration::from_secs(1), health.clone());
+
+        // The wedged exchange only holds up the queue until its own caller gives up, and whoever stopped waiting
+        // meanwhile never reaches the device
+        let (wedged, abandoned, next) = tokio::join!(
+            session.request("wedged", Duration::from_millis(200)),
+            session.request("abandoned", Duration::from_millis(100)),
+            session.request("next", Duration::from_secs(1)),
+        );
+        assert_eq!(wedged, Err(Error::Timeout));
+        assert_eq!(abandoned, Err(Error::Timeout));
+        assert_eq!(next, Ok((1, "next")));
+        assert_eq!(*log.lock().unwrap(), vec!["wedged", "next"]);
+        assert_eq!(opened.load(Ordering::SeqCst), 1);
+        assert_eq!(health.get(), ConnectionState::Connected);
+    }
+
+    #[tokio::test]
+    async fn registry_follows_address_changes() {
+        let info = "<device-info><serial-number>X00000000001</serial-number><friendly-device-name>Den</friendly-device-name></device-info>";
+        let (before, _) = fake_ecp(vec![("GET /query/device-info", info)]).await;
+        let (after, _) = fake_ecp(vec![("GET /query/device-info", info)]).await;
+        let registry = Registry::new(None);
+
+        // The same serial at a new address replaces the old entry
+        assert_eq!(registry.insert(before).await, Ok(String::from("X00000000001")));
+        assert_eq!(registry.insert(after.clone()).await, Ok(String::from("X00000000001")));
+        let entries = registry.list().await;
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].port, entries[0].handle.device().port), (after.port, after.port));
+    }
+
+    #[cfg(feature = "mqtt")]
+    #[test]
+    fn mqtt_commands_and_discovery() {
+        use crate::mqtt::{device_messages, discovery_configs, parse_command, Command, Published};
+        assert_eq!(parse_command("koru", "koru/X123/keypress/set", b"volup"), Some((String::from("X123"), Command::Keypress(Button::VolumeUp))));
+        assert_eq!(parse_command("koru", "koru/10.0.0.2:8060/power/set", b"OFF"), Some((String::from("10.0.0.2:8060"), Command::PowerOff)));
+        assert_eq!(parse_command("koru", "koru/X123/launch/set", b"Netflix"), Some((String::from("X123"), Command::Launch(String::from("Netflix")))));
+        assert_eq!(parse_command("koru", "koru/X123/power/set", b"sideways"), None);
+        assert_eq!(parse_command("koru", "other/X123/keypress/set", b"Home"), None);
+
+        let entry = RegistryEntry {
+            id: String::from("10.0.0.2:8060"),
+            ipv4: String::from("10.0.0.2"),
+            port: 8060,
+            info: DeviceInfo::default(),
+            handle: DeviceHandle::from(Device::new("10.0.0.2", 8060)),
+        };
+        let configs = discovery_configs(&BridgeOptions::default(), &entry);
+        let (topic, power) = &configs[0];
+        assert_eq!(topic, "homeassistant/switch/koru_10_0_0_2_8060/power/config");
+        assert_eq!(power["command_topic"], "koru/10.0.0.2:8060/power/set");
+        assert!(configs.iter().any(|(topic, config)| topic.starts_with("homeassistant/button/") && config["payload_press"] == "Home"));
+
+        let options = BridgeOptions { discovery_prefix: None, ..Default::default() };
+        assert!(discovery_configs(&options, &entry).is_empty());
+
+        // A device that stops answering goes offline (and counts as off) without leaving the registry
+        let message = |topic: &str, payload: &str| (format!("koru/10.0.0.2:8060/{}", topic), String::from(payload));
+        let up = device::Snapshot { power: PowerState::On, ..Default::default() };
+        let update = DeviceUpdate { entry, reachable: true, events: Vec::new(), snapshot: up.clone() };
+        assert!(device_messages(&options, &update, None).contains(&message("availability", "online")));
+        let published = Published { online: true, snapshot: up.clone() };
+        let down = DeviceUpdate { reachable: false, snapshot: device::Snapshot::unreachable(Some(&up)), ..update };
+        assert_eq!(device_messages(&options, &down, Some(&published)), vec![message("availability", "offline"), message("power", "off")]);
+    }
+
+    #[cfg(feature = "mqtt")]
+    #[tokio::test]
+    async fn bridge_to_local_broker() {
+        let info = "<device-info><serial-number>X00000000001</serial-number><power-mode>PowerOn</power-mode></device-info>";
+        let (device, _) = fake_ecp(vec![("GET /query/device-info", info)]).await;
+        let registry = Registry::new(None);
+        registry.insert(device).await.unwrap();
+
+        let (port, published) = fake_broker().await;
+        let options = BridgeOptions { host: String::from("127.0.0.1"), port, client_id: String::from("koru-test-bridge"), ..Default::default() };
+        let bridge = MqttBridge::new(registry.watch(Duration::from_millis(50)), options);
+        tokio::spawn(async move { bridge.run().await });
+
+        let expected = [("koru/status", "online"), ("koru/X00000000001/availability", "online"), ("koru/X00000000001/power", "on")];
+        let arrived = tokio::time::timeout(Duration::from_secs(5), async {
+            while !expected.iter().all(|(t, p)| published.lock().unwrap().contains(&(t.to_string(), p.to_string()))) {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }).await;
+        assert!(arrived.is_ok(), "Bridge only published {:?}", published.lock().unwrap());
+    }
+
+    /// Just enough of an MQTT 3.1.1 broker for one client: acknowledges everything and records each publish
+    #[cfg(feature = "mqtt")]
+    async fn fake_broker() -> (u16, std::sync::Arc<std::sync::Mutex<Vec<(String, String)>>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let published = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = published.clone();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            loop {
+                let mut header = [0u8; 1];
+                if socket.read_exact(&mut header).await.is_err() {
+                    return;
+                }
+                // Remaining length is a base-128 varint
+                let (mut length, mut shift) = (0usize, 0);
+                loop {
+                    let mut byte = [0u8; 1];
+                    socket.read_exact(&mut byte).await.unwrap();
+                    length |= ((byte[0] & 0x7f) as usize) << shift;
+                    shift += 7;
+                    if byte[0] & 0x80 == 0 {
+                        break;
+                    }
+                }
+                let mut body = vec![0u8; length];
+                socket.read_exact(&mut body).await.unwrap();
+
+                let reply = match header[0] >> 4 {
+                    1 => vec![0x20, 0x02, 0x00, 0x00],                          // CONNECT -> CONNACK
+                    3 => {
+                        let topic_length = u16::from_be_bytes([body[0], body[1]]) as usize;
+                        let topic = String::from_utf8_lossy(&body[2..2 + topic_length]).to_string();
+                        let qos = (header[0] >> 1) & 0x03;
+                        let payload_start = 2 + topic_length + if qos > 0 { 2 } else { 0 };
+                        seen.lock().unwrap().push((topic, String::from_utf8_lossy(&body[payload_start..]).to_string()));
+                        match qos {
+                            0 => Vec::new(),
+                            _ => vec![0x40, 0x02, body[2 + topic_length], body[3 + topic_length]],     // PUBACK
+                        }
+                    }
+                    8 => vec![0x90, 0x03, body[0], body[1], 0x01],              // SUBSCRIBE -> SUBACK
+                    12 => vec![0xd0, 0x00],                                     // PINGREQ -> PINGRESP
+                    _ => Vec::new(),
+                };
+                if socket.write_all(&reply).await.is_err() {
+                    return;
+                }
+            }
+        });
+        (port, published)
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn record_metrics() {
+        // Nothing listens on TEST-NET-1, so this fails without touching a real device
+        let mut device = Device::new("192.0.2.1", 8060);
+        device.timeout = Duration::from_millis(100);
+        assert!(device.query_device_info().await.is_err());
+        Metrics::global().observe(&device).await;
+
+        let rendered = Metrics::global().render();
+        assert!(rendered.contains(r#"koru_requests_total{device="192.0.2.1",transport="http"}"#));
+        assert!(rendered.contains(r#"koru_request_failures_total{device="192.0.2.1""#));
+        assert!(rendered.contains(r#"koru_device_reachable{device="192.0.2.1"} 0"#));
+        assert!(rendered.contains(r#"koru_device_power_state{device="192.0.2.1",state="Off"} 1"#));
+
+        // Rounds of registry checks update the gauges without querying devices again
+        let info = "<device-info><serial-number>X00000000002</serial-number><power-mode>PowerOn</power-mode><uptime>42</uptime></device-info>";
+        let (device, requests) = fake_ecp(vec![("GET /query/device-info", info)]).await;
+        let registry = Registry::new(None);
+        registry.insert(device).await.unwrap();
+        let watcher = registry.watch(Duration::from_secs(60));
+        let mut updates = watcher.subscribe();
+        let round = updates.recv().await.unwrap();
+        let checked = requests.lock().unwrap().len();
+        Metrics::global().observe_updates(&round);
+        assert_eq!(requests.lock().unwrap().len(), checked);
+        let rendered = Metrics::global().render();
+        assert!(rendered.contains(r#"koru_device_uptime_seconds{device="127.0.0.1"} 42"#));
+        assert!(rendered.contains(r#"koru_device_power_state{device="127.0.0.1",state="On"} 1"#));
+        // Devices outside the round are forgotten
+        assert!(!rendered.contains(r#"koru_device_reachable{device="192.0.2.1"}"#));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serialize_device() {
+        let mut device = Device::new("10.0.0.2", 8060);
+        device.mac_wol = Some([0xaa, 0xbb, 0xcc, 0x0d, 0x0e, 0x0f]);
+        device.key = Some(b"secret".to_vec());
+        let json = serde_json::to_value(&device).unwrap();
+        assert_eq!(json["mac_wol"], "aa:bb:cc:0d:0e:0f");
+        assert_eq!(json["mac_eth"], serde_json::Value::Null);
+        assert!(json.get("connection").is_none() && json.get("key").is_none());
+
+        let parsed: Device = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed.mac_wol, device.mac_wol);
+        assert_eq!(parsed.key, None);
+        assert_eq!(parsed.timeout, device.timeout);
+
+        // Hand-written JSON only needs the address and network
+        let parsed: Device = serde_json::from_str(r#"{"ipv4": "10.0.0.3", "port": 8060, "network": "Ethernet", "mac_eth": "01-02-03-04-05-06"}"#).unwrap();
+        assert_eq!(parsed.mac_eth, Some([1, 2, 3, 4, 5, 6]));
+        assert!(serde_json::from_str::<Device>(r#"{"ipv4": "10.0.0.3", "port": 8060, "network": "Ethernet", "mac_eth": "01:02"}"#).is_err());
+
+        let buttons: Vec<Button> = serde_json::from_str(r#"["Home", "volup", "Lit_a"]"#).unwrap();
+        assert_eq!(buttons, vec![Button::Home, Button::VolumeUp, Button::from('a')]);
+        assert_eq!(serde_json::to_string(&buttons).unwrap(), r#"["Home","VolumeUp","Lit_-a"]"#);
+        assert_eq!(serde_json::to_string(&PowerState::DisplayOff).unwrap(), r#""DisplayOff""#);
+    }
+
+    #[cfg(feature = "serde")]
+    #[tokio::test]
+    async fn load_device_profiles() {
+        let toml = r#"
+            ecp2_key = "shared"
+            timeout = 5
+
+            [devices.living_room]
+            address = "192.168.1.20"
+            mac_wol = "aa:bb:cc:dd:ee:ff"
+            transport = "http"
+            wol = { broadcast = "192.168.1.255", password = "01:02:03:04" }
+
+            [devices.bedroom]
+            serial = "X00000000001"
+            mac_eth = "not a mac"
+        "#;
+        let env = std::collections::HashMap::from([
+            (String::from("KORU_DEVICES__LIVING_ROOM__ADDRESS"), String::from("192.168.1.21:8061")),
+        ]);
+        let config = KoruConfig::build(
+            ::config::Config::builder().add_source(::config::File::from_str(toml, ::config::FileFormat::Toml)),
+            ::config::Environment::default().source(Some(env)),
+        ).unwrap();
+        assert_eq!(config.ecp2_key.as_deref(), Some("shared"));
+        assert_eq!(config.profile("bedroom").unwrap().serial.as_deref(), Some("X00000000001"));
+
+        let device = config.device("Living_Room").await.unwrap();
+        assert_eq!((device.ipv4.as_str(), device.port), ("192.168.1.21", 8061));
+        assert_eq!(device.name, "living_room");
+        assert_eq!(device.mac_wol, Some([0xaa, 0xbb, 0xcc, 0xdd, 0xee, 0xff]));
+        assert_eq!(device.wol.broadcast, std::net::Ipv4Addr::new(192, 168, 1, 255));
+        assert_eq!(device.wol.password, Some(vec![1, 2, 3, 4]));
+        assert_eq!(device.timeout, Duration::from_secs(5));
+        assert!(!device.is_connected());
+
+        let mut device = Device::new("192.168.1.22", 8060);
+        assert!(matches!(config.profile("bedroom").unwrap().apply("bedroom", &mut device, None), Err(Error::Config(_))));
+        assert!(matches!(config.device("kitchen").await, Err(Error::Config(_))));
+
+        // A provider handed over in code takes the place of the shared key, and keys stay out of Debug output
+        assert!(!format!("{:?}", config).contains("shared"));
+        let config = config.with_key_provider(Box::new(InlineKey::new("injected")));
+        assert_eq!(config.key_for(config.profile("bedroom").unwrap()).unwrap().key().unwrap(), b"injected");
+        let profile = DeviceProfile { key: Some(String::from("own")), ..Default::default() };
+        assert_eq!(config.key_for(&profile).unwrap().key().unwrap(), b"own");
+        assert!(!format!("{:?}", profile).contains("own"));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn load_keys() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = std::env::temp_dir().join(format!("koru-keys-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let plain = dir.join("plain");
+        std::fs::write(&plain, "secret\n").unwrap();
+        std::fs::set_permissions(&plain, std::fs::Permissions::from_mode(0o644)).unwrap();
+        assert!(matches!(FileKey::new(&plain).key(), Err(Error::Key(_))));
+        std::fs::set_permissions(&plain, std::fs::Permissions::from_mode(0o640)).unwrap();
+        assert!(matches!(FileKey::new(&plain).key(), Err(Error::Key(_))));
+        std::fs::set_permissions(&plain, std::fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(FileKey::new(&plain).key().unwrap(), b"secret");
+
+        assert_eq!(AnyFileKey::new(&plain, None).key().unwrap(), b"secret");
+        assert!(EnvKey::new("KORU_TEST_UNSET_KEY").key().is_err());
+
+        // Keys can be handed over directly instead of through the environment, and never show up in Debug output
+        let inline = InlineKey::new("from-code");
+        assert_eq!(inline.key().unwrap(), b"from-code");
+        assert!(!format!("{:?}", inline).contains("from-code"));
+        assert!(InlineKey::new("").key().is_err());
+
+        #[cfg(feature = "encryption")]
+        {
+            let encrypted = dir.join("encrypted");
+            let _ = std::fs::remove_file(&encrypted);
+            EncryptedFileKey::create(&encrypted, b"secret", "correct horse").unwrap();
+            assert!(is_encrypted_key_file(&encrypted));
+            assert!(!std::fs::read(&encrypted).unwrap().windows(6).any(|w| w == b"secret"));
+            assert_eq!(std::fs::metadata(&encrypted).unwrap().permissions().mode() & 0o777, 0o600);
+            assert_eq!(load_key_file(&encrypted, Some("correct horse")).unwrap(), b"secret");
+            assert_eq!(AnyFileKey::new(&encrypted, Some("correct horse")).key().unwrap(), b"secret");
+            assert!(!format!("{:?}", AnyFileKey::new(&encrypted, Some("correct horse"))).contains("horse"));
+            assert!(load_key_file(&encrypted, Some("wrong")).is_err());
+            assert!(load_key_file(&encrypted, None).is_err());
+            // Never overwrites an existing key file
+            assert!(EncryptedFileKey::create(&encrypted, b"other", "correct horse").is_err());
+        }
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[tokio::test]
+    async fn fan_out_to_group() {
+        let mut group = DeviceGroup::new();
+        group.add("left", Device::new("192.0.2.1", 8060)).add("right", Device::new("192.0.2.2", 8060));
+        assert_eq!(group.members(), vec!["left", "right"]);
+
+        // Unconnected members fail on their own without holding up the group
+        let results = group.press_button(Button::Home).await;
+        assert_eq!(results.results.len(), 2);
+        assert!(!results.all_ok());
+        assert_eq!(results.get("right"), Some(&Err(Error::NotConnected)));
+
+        group.lock_step = true;
+        let results = tokio::time::timeout(Duration::from_secs(1), group.type_text("hello")).await.unwrap();
+        assert_eq!(results.failures().map(|(name, _)| name).collect::<Vec<_>>(), vec!["left", "right"]);
+
+        assert!(group.remove("left").is_some());
+        assert_eq!(group.members(), vec!["right"]);
+    }
+
+    #[tokio::test]
+    async fn lock_step_group() {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        // Plain ECP members, so no ECP-2 connection is needed
+        let http = |mut device: Device| {
+            let mut raw = std::collections::HashMap::new();
+            raw.insert(String::from("is-tv"), String::from("false"));
+            device.info = Some(DeviceInfo::from(raw));
+            device.transport = Transport::Http;
+            device
+        };
+
+        // Both members answer on one endpoint, so its log shows the order keys arrived in across them
+        let (device, requests) = fake_ecp(vec![]).await;
+        let port = device.port;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let gone = Device::new("127.0.0.1", listener.local_addr().unwrap().port() as i32);
+        let log = requests.clone();
+        tokio::spawn(async move {
+            // Slowly answers the first key, then goes away
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let _ = socket.read(&mut [0; 1024]).await;
+            tokio::time::sleep(Duration::from_millis(1000)).await;
+            log.lock().unwrap().push(String::from("gone answered"));
+            let _ = socket.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await;
+        });
+
+        let mut group = DeviceGroup::new();
+        group.add("left", http(device)).add("right", http(Device::new("127.0.0.1", port))).add("gone", http(gone));
+        group.lock_step = true;
+        group.delay = Duration::from_millis(10);
+        let results = tokio::time::timeout(Duration::from_secs(5), group.press_buttons(vec![Button::Up, Button::Down, Button::Left])).await.unwrap();
+
+        // Every key reached every member before the next was sent, and the member that went away didn't hold up the rest
+        assert_eq!(*requests.lock().unwrap(), vec![
+            "POST /keypress/Up", "POST /keypress/Up", "gone answered",
+            "POST /keypress/Down", "POST /keypress/Down",
+            "POST /keypress/Left", "POST /keypress/Left",
+        ]);
+        assert_eq!(results.get("left"), Some(&Ok(())));
+        assert_eq!(results.get("right"), Some(&Ok(())));
+        assert!(matches!(results.get("gone"), Some(Err(Error::Unreachable(_)))));
+    }
+
+    // KORU_ECP2_KEY, or ecp2_key/ecp2_key_file in conf/secrets
     #[allow(dead_code)]
     fn load_ecp2_key() -> Vec<u8> {
-        let config = config::load_from_file("conf/secrets");
-        assert!(config.len() > 0);
-        assert!(config.contains_key("ecp2_key"));
-        let key = config.get("ecp2_key").unwrap().as_bytes().to_vec();
-        key
+        if let Ok(key) = EnvKey::default().key() {
+            return key;
+        }
+        #[cfg(not(feature = "serde"))]
+        panic!("KORU_ECP2_KEY isn't set, and reading conf/secrets needs the serde feature");
+        #[cfg(feature = "serde")]
+        {
+            let config = KoruConfig::load("conf/secrets").unwrap();
+            match (config.ecp2_key, config.ecp2_key_file) {
+                (Some(key), _) => key.into_bytes(),
+                (None, Some(path)) => load_key_file(path, config.key_passphrase.as_deref()).unwrap(),
+                (None, None) => panic!("conf/secrets has no ecp2_key"),
+            }
+        }
     }
 
     #[tokio::test]
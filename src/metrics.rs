@@ -0,0 +1,192 @@
+//! Prometheus metrics: per-device gauges and client request counters
+
+use crate::{App, Device, DeviceInfo, DeviceUpdate, Error, PowerState, RegistryWatcher};
+use prometheus::core::Collector;
+use prometheus::{Encoder, HistogramOpts, HistogramVec, IntCounterVec, IntGaugeVec, Opts, TextEncoder};
+use std::sync::OnceLock;
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+// Request latency buckets, in seconds
+const LATENCY_BUCKETS: &[f64] = &[0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
+// Every power state, so the power gauge always has a series per state
+const POWER_STATES: &[PowerState] = &[
+    PowerState::Off, PowerState::DisplayOff, PowerState::Ready, PowerState::Headless, PowerState::On, PowerState::Unknown,
+];
+
+/// Every koru metric, registered in its own Prometheus registry
+// Devices are labelled by IP address, since that's all a Device is guaranteed to know about itself
+pub struct Metrics {
+    registry:       prometheus::Registry,
+    reachable:      IntGaugeVec,    // device
+    power:          IntGaugeVec,    // device, state
+    uptime:         IntGaugeVec,    // device
+    active_app:     IntGaugeVec,    // device
+    info:           IntGaugeVec,    // device, serial, name, model, software
+    requests:       IntCounterVec,  // device, transport
+    failures:       IntCounterVec,  // device, transport, kind
+    latency:        HistogramVec,   // device, transport
+    reconnects:     IntCounterVec,  // device
+}
+
+impl Metrics {
+    /// The metrics the library records into
+    pub fn global() -> &'static Metrics {
+        static METRICS: OnceLock<Metrics> = OnceLock::new();
+        METRICS.get_or_init(Metrics::new)
+    }
+
+    fn new() -> Metrics {
+        let gauge = |name: &str, help: &str, labels: &[&str]| IntGaugeVec::new(Opts::new(name, help), labels).unwrap();
+        let counter = |name: &str, help: &str, labels: &[&str]| IntCounterVec::new(Opts::new(name, help), labels).unwrap();
+
+        let metrics = Metrics {
+            registry: prometheus::Registry::new(),
+            reachable: gauge("koru_device_reachable", "Whether the device answered its last check", &["device"]),
+            power: gauge("koru_device_power_state", "1 for the device's current power state", &["device", "state"]),
+            uptime: gauge("koru_device_uptime_seconds", "Uptime reported by device-info", &["device"]),
+            active_app: gauge("koru_device_active_app", "Id of the app in the foreground, 0 for the home screen", &["device"]),
+            info: gauge("koru_device_info", "Device details, always 1", &["device", "serial", "name", "model", "software"]),
+            requests: counter("koru_requests_total", "Requests sent to devices", &["device", "transport"]),
+            failures: counter("koru_request_failures_total", "Requests that failed, by error kind", &["device", "transport", "kind"]),
+            latency: HistogramVec::new(
+                HistogramOpts::new("koru_request_duration_seconds", "Time taken for devices to answer requests")
+                    .buckets(LATENCY_BUCKETS.to_vec()),
+                &["device", "transport"],
+            ).unwrap(),
+            reconnects: counter("koru_reconnects_total", "ECP-2 reconnect attempts", &["device"]),
+        };
+
+        let collectors: Vec<Box<dyn Collector>> = vec![
+            Box::new(metrics.reachable.clone()), Box::new(metrics.power.clone()), Box::new(metrics.uptime.clone()),
+            Box::new(metrics.active_app.clone()), Box::new(metrics.info.clone()), Box::new(metrics.requests.clone()),
+            Box::new(metrics.failures.clone()), Box::new(metrics.latency.clone()), Box::new(metrics.reconnects.clone()),
+        ];
+        for collector in collectors {
+            metrics.registry.register(collector).unwrap();
+        }
+        metrics
+    }
+
+    /// Check a device and update its gauges
+    pub async fn observe(&self, device: &Device) {
+        let info = device.query_device_info().await.ok();
+        let app = device.get_active_app().await.ok();
+        self.record_device(&device.ipv4, info.as_ref(), app.as_ref().map(Option::as_ref));
+    }
+
+    /// Update gauges from every round of a watcher's checks, until the watcher stops
+    pub async fn observe_watcher(&self, watcher: &RegistryWatcher) {
+        let mut updates = watcher.subscribe();
+        loop {
+            match updates.recv().await {
+                Ok(round) => self.observe_updates(&round),
+                // Only the latest state matters, and the next round has it
+                Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    }
+
+    /// Update gauges from one round of checks, dropping gauges for devices that left the registry
+    pub fn observe_updates(&self, round: &[DeviceUpdate]) {
+        let addresses: Vec<&str> = round.iter().map(|u| u.entry.ipv4.as_str()).collect();
+        self.forget_devices_except(&addresses);
+        for update in round {
+            let info = update.snapshot.info.as_ref().filter(|_| update.reachable);
+            let app = update.reachable.then_some(update.snapshot.app.as_ref());
+            self.record_device(&update.entry.ipv4, info, app);
+        }
+    }
+
+    /// Set a device's gauges from its device-info (None if it didn't answer) and active app (None if unknown)
+    fn record_device(&self, name: &str, info: Option<&DeviceInfo>, app: Option<Option<&App>>) {
+        match info {
+            Some(info) => {
+                self.reachable.with_label_values(&[name]).set(1);
+                for state in POWER_STATES {
+                    let current = (*state == info.power_mode) as i64;
+                    self.power.with_label_values(&[name, &state.to_string()]).set(current);
+                }
+                if let Some(uptime) = info.uptime {
+                    self.uptime.with_label_values(&[name]).set(uptime as i64);
+                }
+                self.info.with_label_values(&[name, &info.serial_number, &info.friendly_name, &info.model_name, &info.software_version]).set(1);
+            }
+            None => {
+                self.reachable.with_label_values(&[name]).set(0);
+                for state in POWER_STATES {
+                    self.power.with_label_values(&[name, &state.to_string()]).set((*state == PowerState::Off) as i64);
+                }
+            }
+        }
+        if let Some(app) = app {
+            self.active_app.with_label_values(&[name]).set(app.map(|a| a.id as i64).unwrap_or(0));
+        }
+    }
+
+    /// Everything in the Prometheus text format, for a /metrics endpoint
+    pub fn render(&self) -> String {
+        let mut buffer = Vec::new();
+        let _ = TextEncoder::new().encode(&self.registry.gather(), &mut buffer);
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+
+    /// Count a request and how it went
+    pub(crate) fn record_request<T>(&self, device: &str, transport: &str, took: Duration, result: &Result<T, Error>) {
+        self.requests.with_label_values(&[device, transport]).inc();
+        self.latency.with_label_values(&[device, transport]).observe(took.as_secs_f64());
+        if let Err(e) = result {
+            self.failures.with_label_values(&[device, transport, error_kind(e)]).inc();
+        }
+    }
+
+    pub(crate) fn record_reconnect(&self, device: &str) {
+        self.reconnects.with_label_values(&[device]).inc();
+    }
+
+    /// Drop per-device gauges for any device not listed
+    fn forget_devices_except(&self, devices: &[&str]) {
+        let known = device_labels(&self.reachable);
+        for device in known.iter().filter(|d| !devices.contains(&d.as_str())) {
+            let _ = self.reachable.remove_label_values(&[device]);
+            let _ = self.uptime.remove_label_values(&[device]);
+            let _ = self.active_app.remove_label_values(&[device]);
+            for state in POWER_STATES {
+                let _ = self.power.remove_label_values(&[device, &state.to_string()]);
+            }
+        }
+        // The info gauge has more labels than just the device, so start it over for the remaining devices
+        if known.iter().any(|d| !devices.contains(&d.as_str())) {
+            self.info.reset();
+        }
+    }
+}
+
+/// Label for the kind of error a request failed with
+pub(crate) fn error_kind(e: &Error) -> &'static str {
+    match e {
+        Error::NotConnected => "not_connected",
+        Error::NoResponse => "no_response",
+        Error::Timeout => "timeout",
+        Error::EmptyResponse => "empty_response",
+        Error::Unsupported(_) => "unsupported",
+        Error::InvalidRequest(_) => "invalid_request",
+        Error::Unreachable(_) => "unreachable",
+        Error::Http(_) => "http",
+        Error::Discovery(_) => "discovery",
+        Error::WakeOnLan(_) => "wake_on_lan",
+        Error::Parse(_) => "parse",
+        Error::Mqtt(_) => "mqtt",
+        Error::Config(_) => "config",
+        Error::Key(_) => "key",
+    }
+}
+
+/// Device label values a gauge has series for
+fn device_labels(gauge: &IntGaugeVec) -> Vec<String> {
+    gauge.collect().iter()
+        .flat_map(|family| family.get_metric().iter())
+        .filter_map(|metric| metric.get_label().iter().find(|l| l.get_name() == "device").map(|l| l.get_value().to_string()))
+        .collect()
+}
@@ -0,0 +1,307 @@
+//! MQTT bridge: device state on retained topics, commands from `.../set` topics, Home Assistant discovery
+//!
+//! Topics, for a device with registry id `{id}` under the default `koru` prefix:
+//! * `koru/status`                 bridge availability (`online`/`offline`, the latter as last will)
+//! * `koru/{id}/availability`      device availability (`online`, or `offline` when it stops answering or leaves the registry)
+//! * `koru/{id}/power`             `on`/`off`
+//! * `koru/{id}/app`               active app name (`Home` if none)
+//! * `koru/{id}/media`             media player state (`play`, `pause`, ...)
+//! * `koru/{id}/keypress/set`      button to press, e.g. `Home` or `Lit_a`
+//! * `koru/{id}/launch/set`        app id or name to launch
+//! * `koru/{id}/power/set`         `on`, `off` or `toggle`
+//! * `koru/{id}/error`             why the last command failed
+
+use crate::device::Snapshot;
+use crate::{Button, DeviceUpdate, Error, Registry, RegistryEntry, RegistryWatcher};
+use rumqttc::{AsyncClient, Event, LastWill, MqttOptions, Packet, QoS};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::broadcast;
+
+// Requests that can queue up for the MQTT event loop
+const CLIENT_CAPACITY: usize = 64;
+// How long to wait before reconnecting to the broker
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+// Buttons exposed to Home Assistant as button entities
+const HOME_ASSISTANT_BUTTONS: &[Button] = &[
+    Button::Home, Button::Back, Button::Select, Button::Up, Button::Down, Button::Left, Button::Right,
+    Button::PlayPause, Button::Rewind, Button::Forward, Button::InstantReplay, Button::Info,
+    Button::VolumeUp, Button::VolumeDown, Button::VolumeMute,
+];
+
+/// Broker connection and topic layout for an MqttBridge
+#[derive(Clone, Debug)]
+pub struct BridgeOptions {
+    pub host:               String,
+    pub port:               u16,
+    pub client_id:          String,
+    pub credentials:        Option<(String, String)>,   // Username and password
+    pub prefix:             String,                     // Root of the bridge's own topics
+    pub discovery_prefix:   Option<String>,             // Home Assistant discovery prefix, None to skip discovery
+}
+
+impl Default for BridgeOptions {
+    fn default() -> Self {
+        BridgeOptions {
+            host: String::from("localhost"),
+            port: 1883,
+            client_id: String::from("koru-bridge"),
+            credentials: None,
+            prefix: String::from("koru"),
+            discovery_prefix: Some(String::from("homeassistant")),
+        }
+    }
+}
+
+/// Commands received on `.../set` topics
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub(crate) enum Command {
+    Keypress(Button),
+    Launch(String),
+    PowerOn,
+    PowerOff,
+    TogglePower,
+}
+
+/// What was last published for a device
+#[derive(Clone)]
+pub(crate) struct Published {
+    pub(crate) online:      bool,
+    pub(crate) snapshot:    Snapshot,
+}
+
+/// Publishes the state of every device a RegistryWatcher checks to MQTT and runs commands sent back
+// NOTE: The registry isn't refreshed by the bridge, that's up to whoever owns it (e.g. koru-server)
+pub struct MqttBridge {
+    watcher:    RegistryWatcher,
+    options:    BridgeOptions,
+}
+
+impl MqttBridge {
+    pub fn new(watcher: RegistryWatcher, options: BridgeOptions) -> MqttBridge {
+        MqttBridge { watcher, options }
+    }
+
+    /// Run the bridge, failing if the broker can't be reached at first and reconnecting after that
+    pub async fn run(&self) -> Result<(), Error> {
+        let (client, mut events) = AsyncClient::new(self.mqtt_options(), CLIENT_CAPACITY);
+        // Last-published state per device, cleared on (re)connect so everything is published again
+        let published: Arc<Mutex<HashMap<String, Published>>> = Default::default();
+
+        let publisher = tokio::spawn(publish_devices(
+            self.watcher.subscribe(), client.clone(), self.options.clone(), published.clone(),
+        ));
+        let mut connected = false;
+        let result = loop {
+            match events.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    connected = true;
+                    published.lock().unwrap().clear();
+                    let subscribed = client.try_subscribe(format!("{}/+/+/set", self.options.prefix), QoS::AtLeastOnce);
+                    let announced = client.try_publish(self.status_topic(), QoS::AtLeastOnce, true, "online");
+                    if let Err(e) = subscribed.and(announced) {
+                        break Err(Error::Mqtt(e.to_string()));
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    if let Some((id, command)) = parse_command(&self.options.prefix, &publish.topic, &publish.payload) {
+                        let registry = self.watcher.registry().clone();
+                        let client = client.clone();
+                        let prefix = self.options.prefix.clone();
+                        tokio::spawn(async move {
+                            if let Err(e) = run_command(&registry, &id, command).await {
+                                let _ = client.publish(format!("{}/{}/error", prefix, id), QoS::AtMostOnce, false, e.to_string()).await;
+                            }
+                        });
+                    }
+                }
+                Ok(_) => (),
+                Err(e) if !connected => break Err(Error::Mqtt(e.to_string())),
+                // The event loop reconnects on the next poll
+                Err(_) => tokio::time::sleep(RECONNECT_DELAY).await,
+            }
+        };
+        publisher.abort();
+        result
+    }
+
+    fn mqtt_options(&self) -> MqttOptions {
+        let mut options = MqttOptions::new(&self.options.client_id, &self.options.host, self.options.port);
+        options.set_keep_alive(Duration::from_secs(30));
+        options.set_last_will(LastWill::new(self.status_topic(), "offline", QoS::AtLeastOnce, true));
+        if let Some((username, password)) = &self.options.credentials {
+            options.set_credentials(username, password);
+        }
+        options
+    }
+
+    fn status_topic(&self) -> String {
+        format!("{}/status", self.options.prefix)
+    }
+}
+
+/// Publish discovery, availability and state changes from each round of device updates
+async fn publish_devices(
+    mut updates: broadcast::Receiver<Arc<Vec<DeviceUpdate>>>,
+    client: AsyncClient,
+    options: BridgeOptions,
+    published: Arc<Mutex<HashMap<String, Published>>>,
+) {
+    loop {
+        let round = match updates.recv().await {
+            Ok(round) => round,
+            // Only the latest state matters, and the next round has it
+            Err(broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(broadcast::error::RecvError::Closed) => return,
+        };
+
+        for update in round.iter() {
+            let previous = published.lock().unwrap().get(&update.entry.id).cloned();
+            for (topic, payload) in device_messages(&options, update, previous.as_ref()) {
+                if client.publish(topic, QoS::AtLeastOnce, true, payload).await.is_err() {
+                    return;
+                }
+            }
+            let next = Published { online: update.reachable, snapshot: update.snapshot.clone() };
+            published.lock().unwrap().insert(update.entry.id.clone(), next);
+        }
+
+        // Devices that left the registry stay in Home Assistant, just unavailable
+        let gone: Vec<String> = published.lock().unwrap().keys()
+            .filter(|id| !round.iter().any(|u| &u.entry.id == *id))
+            .cloned()
+            .collect();
+        for id in gone {
+            published.lock().unwrap().remove(&id);
+            let topic = format!("{}/{}/availability", options.prefix, id);
+            if client.publish(topic, QoS::AtLeastOnce, true, "offline").await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Retained messages for whatever changed since a device was last published (everything, the first time)
+pub(crate) fn device_messages(options: &BridgeOptions, update: &DeviceUpdate, previous: Option<&Published>) -> Vec<(String, String)> {
+    let topic = |name: &str| format!("{}/{}/{}", options.prefix, update.entry.id, name);
+    let mut messages = Vec::new();
+
+    if previous.is_none() {
+        messages.extend(discovery_configs(options, &update.entry).into_iter().map(|(t, config)| (t, config.to_string())));
+    }
+    if previous.map(|p| p.online) != Some(update.reachable) {
+        messages.push((topic("availability"), String::from(if update.reachable { "online" } else { "offline" })));
+    }
+
+    let (previous, next) = (previous.map(|p| &p.snapshot), &update.snapshot);
+    let changed = |field: fn(&Snapshot) -> String| {
+        let value = field(next);
+        (previous.map(field) != Some(value.clone())).then_some(value)
+    };
+    if let Some(power) = changed(|s| String::from(if s.power.is_off() { "off" } else { "on" })) {
+        messages.push((topic("power"), power));
+    }
+    if let Some(app) = changed(|s| s.app.as_ref().map(|a| a.name.clone()).unwrap_or(String::from("Home"))) {
+        messages.push((topic("app"), app));
+    }
+    if let Some(media) = changed(|s| s.media.to_string()) {
+        messages.push((topic("media"), media));
+    }
+    messages
+}
+
+/// Run a command against a registered device
+async fn run_command(registry: &Registry, id: &str, command: Command) -> Result<(), Error> {
+    let entry = registry.get(id).await
+        .ok_or_else(|| Error::InvalidRequest(format!("No device with id \"{}\"", id)))?;
+    match command {
+        Command::Keypress(button) => entry.handle.press_button(button).await,
+        Command::Launch(app) => entry.handle.launch_app(&app).await.map(|_| ()),
+        Command::PowerOn => entry.handle.power_on().await.map(|_| ()),
+        Command::PowerOff => entry.handle.power_off().await.map(|_| ()),
+        Command::TogglePower => entry.handle.toggle_power_state().await.map(|_| ()),
+    }
+}
+
+/// Device id and command from a `{prefix}/{id}/{command}/set` message
+pub(crate) fn parse_command(prefix: &str, topic: &str, payload: &[u8]) -> Option<(String, Command)> {
+    let rest = topic.strip_prefix(prefix)?.strip_prefix('/')?.strip_suffix("/set")?;
+    let (id, name) = rest.rsplit_once('/')?;
+    let payload = std::str::from_utf8(payload).ok()?.trim();
+
+    let command = match name {
+        "keypress" => Command::Keypress(payload.parse().ok()?),
+        "launch" if !payload.is_empty() => Command::Launch(String::from(payload)),
+        "power" => match payload.to_ascii_lowercase().as_str() {
+            "on" => Command::PowerOn,
+            "off" => Command::PowerOff,
+            "toggle" => Command::TogglePower,
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some((String::from(id), command))
+}
+
+/// Home Assistant discovery topics and configs for a device
+// Home Assistant's MQTT integration has no media_player or remote platform, so a device shows up as
+// a power switch, active app and media state sensors, a text entity for launching apps and buttons
+pub(crate) fn discovery_configs(options: &BridgeOptions, entry: &RegistryEntry) -> Vec<(String, Value)> {
+    let Some(discovery_prefix) = &options.discovery_prefix else { return Vec::new() };
+    let node = node_id(&entry.id);
+    let topic = |name: &str| format!("{}/{}/{}", options.prefix, entry.id, name);
+    let device = json!({
+        "identifiers": [node],
+        "name": entry.info.friendly_name,
+        "manufacturer": "Roku",
+        "model": entry.info.model_name,
+        "serial_number": entry.info.serial_number,
+        "sw_version": entry.info.software_version,
+    });
+    let availability = json!([
+        { "topic": format!("{}/status", options.prefix) },
+        { "topic": topic("availability") },
+    ]);
+    let config = |component: &str, object: &str, name: &str, fields: Value| {
+        let mut config = json!({
+            "name": name,
+            "unique_id": format!("{}_{}", node, object),
+            "device": device,
+            "availability": availability,
+            "availability_mode": "all",
+        });
+        if let (Some(config), Some(fields)) = (config.as_object_mut(), fields.as_object()) {
+            config.extend(fields.clone());
+        }
+        (format!("{}/{}/{}/{}/config", discovery_prefix, component, node, object), config)
+    };
+
+    let mut configs = vec![
+        config("switch", "power", "Power", json!({
+            "state_topic": topic("power"),
+            "command_topic": topic("power/set"),
+            "payload_on": "on", "payload_off": "off",
+            "state_on": "on", "state_off": "off",
+        })),
+        config("sensor", "app", "Active app", json!({ "state_topic": topic("app"), "icon": "mdi:application" })),
+        config("sensor", "media", "Media state", json!({ "state_topic": topic("media"), "icon": "mdi:play-pause" })),
+        config("text", "launch", "Launch app", json!({ "command_topic": topic("launch/set"), "mode": "text" })),
+    ];
+    for button in HOME_ASSISTANT_BUTTONS {
+        let key = button.to_string();
+        configs.push(config("button", &key.to_ascii_lowercase(), &key, json!({
+            "command_topic": topic("keypress/set"),
+            "payload_press": key,
+        })));
+    }
+    configs
+}
+
+/// Registry id made safe for Home Assistant ids and discovery topics
+fn node_id(id: &str) -> String {
+    let id: String = id.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect();
+    format!("koru_{}", id)
+}
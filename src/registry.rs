@@ -0,0 +1,215 @@
+use crate::device::Snapshot;
+use crate::{discover_devices, Device, DeviceEvent, DeviceHandle, DeviceInfo, Error, KeyProvider};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::{broadcast, RwLock};
+use tokio::task::JoinSet;
+
+// How many rounds of updates a slow subscriber can fall behind before it starts missing them
+const UPDATE_BUFFER: usize = 16;
+
+/// A device known to the registry
+#[derive(Clone)]
+pub struct RegistryEntry {
+    pub id:         String,         // Stable id (serial number, or address if the device doesn't report one)
+    pub ipv4:       String,         // Last-known IPv4 address
+    pub port:       i32,            // Last-known port
+    pub info:       DeviceInfo,     // device-info from when it was last seen
+    pub handle:     DeviceHandle,   // Shared handle for controlling it
+}
+
+/// Devices that came, went or moved during a refresh
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RegistryChanges {
+    pub added:      Vec<String>,            // Ids of newly discovered devices
+    pub removed:    Vec<String>,            // Ids of devices that are gone
+    pub moved:      Vec<String>,            // Ids of devices now at a different address (e.g. a new DHCP lease)
+    pub failed:     Vec<(String, Error)>,   // Ids of devices that were found but couldn't be connected to
+}
+
+/// What one check found for a registered device
+#[derive(Clone)]
+pub struct DeviceUpdate {
+    pub entry:          RegistryEntry,      // The device as registered when it was checked
+    pub reachable:      bool,               // Whether it answered device-info
+    pub events:         Vec<DeviceEvent>,   // Changes since the previous check (none on the first)
+    pub(crate) snapshot: Snapshot,          // State it was found in (Off if it didn't answer)
+}
+
+/// Checks every registered device on one schedule and shares each round of updates with every subscriber
+// The event feed, MQTT bridge and metrics all read from one of these instead of each polling every device.
+// Polling stops once every clone has been dropped.
+#[derive(Clone)]
+pub struct RegistryWatcher {
+    registry:   Registry,
+    updates:    Arc<broadcast::Sender<Arc<Vec<DeviceUpdate>>>>,
+}
+
+/// Keeps track of the devices on the network, for long-running services (gateways, bridges, ...)
+#[derive(Clone)]
+pub struct Registry {
+    devices:        Arc<RwLock<HashMap<String, RegistryEntry>>>,
+    key:            Option<Arc<dyn KeyProvider>>,   // Where the ECP-2 key for newly discovered devices comes from
+}
+
+impl Registry {
+    /// Constructor w/ the provider of the ECP-2 key to connect with (if any)
+    pub fn new(key: Option<Box<dyn KeyProvider>>) -> Registry {
+        Registry {
+            devices: Arc::new(RwLock::new(HashMap::new())),
+            key: key.map(Arc::from),
+        }
+    }
+
+    /// Discover devices, adding new ones, following ones that moved and removing ones that no longer answer
+    pub async fn refresh(&self, timeout: Duration) -> Result<RegistryChanges, Error> {
+        let discovered = discover_devices(timeout).await
+            .map_err(|e| Error::Discovery(e.to_string()))?;
+
+        let mut changes = RegistryChanges::default();
+        let mut seen = Vec::new();
+        for device in discovered {
+            let info = match device.query_device_info().await {
+                Ok(info) => info,
+                Err(_) => continue,
+            };
+            let id = entry_id(&device, &info);
+            seen.push(id.clone());
+            let known = self.devices.read().await.get(&id).map(|entry| (entry.ipv4.clone(), entry.port));
+            let moved = match known {
+                Some((ipv4, port)) if ipv4 == device.ipv4 && port == device.port => continue,
+                Some(_) => true,
+                None => false,
+            };
+            // A device that couldn't be connected to keeps its old entry (if any) and is tried again next time
+            match self.insert_with_info(device, info).await {
+                Ok(_) if moved => changes.moved.push(id),
+                Ok(_) => changes.added.push(id),
+                Err(e) => changes.failed.push((id, e)),
+            }
+        }
+
+        // Devices missing from discovery may just have been slow to answer, so check before dropping them
+        let missing: Vec<RegistryEntry> = self.devices.read().await.values()
+            .filter(|entry| !seen.contains(&entry.id))
+            .cloned()
+            .collect();
+        for entry in missing {
+            if !Device::new(&entry.ipv4, entry.port).is_reachable().await {
+                self.devices.write().await.remove(&entry.id);
+                changes.removed.push(entry.id);
+            }
+        }
+        Ok(changes)
+    }
+
+    /// Add a device by hand (e.g. one that isn't discoverable), returning its id
+    pub async fn insert(&self, device: Device) -> Result<String, Error> {
+        let info = device.query_device_info().await?;
+        self.insert_with_info(device, info).await
+    }
+
+    /// Look up a device by id
+    pub async fn get(&self, id: &str) -> Option<RegistryEntry> {
+        self.devices.read().await.get(id).cloned()
+    }
+
+    /// Every known device, sorted by id
+    pub async fn list(&self) -> Vec<RegistryEntry> {
+        let mut entries: Vec<RegistryEntry> = self.devices.read().await.values().cloned().collect();
+        entries.sort_by(|a, b| a.id.cmp(&b.id));
+        entries
+    }
+
+    /// Check every device each interval, all at once, sharing the results through the returned watcher
+    pub fn watch(&self, interval: Duration) -> RegistryWatcher {
+        let updates = Arc::new(broadcast::channel(UPDATE_BUFFER).0);
+        let registry = self.clone();
+        let sender = Arc::downgrade(&updates);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            let mut previous = HashMap::new();
+            loop {
+                ticker.tick().await;
+                let round = registry.check_devices(&mut previous).await;
+                match sender.upgrade() {
+                    Some(sender) => { let _ = sender.send(Arc::new(round)); }
+                    None => break,
+                }
+            }
+        });
+        RegistryWatcher { registry: self.clone(), updates }
+    }
+
+    /// Snapshot every device at once through its handle, noting what changed since the previous round
+    async fn check_devices(&self, previous: &mut HashMap<String, Snapshot>) -> Vec<DeviceUpdate> {
+        let mut checks = JoinSet::new();
+        for entry in self.list().await {
+            let last = previous.get(&entry.id).cloned();
+            checks.spawn(async move {
+                let result = entry.handle.device().snapshot(last.as_ref()).await;
+                (entry, last, result)
+            });
+        }
+
+        let mut round = Vec::new();
+        while let Some(checked) = checks.join_next().await {
+            let Ok((entry, last, result)) = checked else { continue };
+            let reachable = result.is_ok();
+            let snapshot = result.unwrap_or_else(|_| Snapshot::unreachable(last.as_ref()));
+            let events = last.map(|last| last.diff(&snapshot)).unwrap_or_default();
+            round.push(DeviceUpdate { entry, reachable, events, snapshot });
+        }
+        round.sort_by(|a, b| a.entry.id.cmp(&b.entry.id));
+
+        // Devices that left the registry start over if they come back
+        previous.clear();
+        previous.extend(round.iter().map(|update| (update.entry.id.clone(), update.snapshot.clone())));
+        round
+    }
+
+    /// Connect (if there's a key) and store a device, replacing any entry with the same id
+    async fn insert_with_info(&self, mut device: Device, info: DeviceInfo) -> Result<String, Error> {
+        let id = entry_id(&device, &info);
+        // Asked for every device, so e.g. a rotated key file is picked up
+        if let Some(provider) = &self.key {
+            if !device.connect(provider.key()?).await {
+                return Err(Error::NotConnected);
+            }
+        }
+        device.name = info.friendly_name.clone();
+        device.info = Some(info.clone());
+
+        let entry = RegistryEntry {
+            id: id.clone(),
+            ipv4: device.ipv4.clone(),
+            port: device.port,
+            info,
+            handle: device.share(),
+        };
+        self.devices.write().await.insert(id.clone(), entry);
+        Ok(id)
+    }
+}
+
+impl RegistryWatcher {
+    /// The registry being watched
+    pub fn registry(&self) -> &Registry {
+        &self.registry
+    }
+
+    /// Receive every round of updates from now on, each covering every device registered at the time
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Vec<DeviceUpdate>>> {
+        self.updates.subscribe()
+    }
+}
+
+/// Registry id for a device: its serial number, or its address if it has none
+fn entry_id(device: &Device, info: &DeviceInfo) -> String {
+    if info.serial_number.is_empty() {
+        format!("{}:{}", device.ipv4, device.port)
+    } else {
+        info.serial_number.clone()
+    }
+}
@@ -1,9 +1,16 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Prefix for keyboard literals, e.g. "Lit_-a"
+const LITERAL_PREFIX: &str = "Lit_-";
+
 /// All known remote buttons                                                                <br/>
 /// Legend:                                                                                 <br/>
 ///     * Optional - Requires device support                                                <br/>
 ///     * Android - Not officially documented, but found in Android source                  <br/>
 ///     * Undocumented - Not documented at all, but appears to work on my devices (^.^')    <br/>
 #[allow(dead_code)]
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum Button {
     Home,
     Rewind,
@@ -59,132 +66,226 @@ pub enum Button {
     Literal { character: char, },
 }
 
-impl ToString for Button {
-    fn to_string(&self) -> String {
+impl Button {
+    /// Every named (non-literal) button
+    pub const ALL: [Button; 51] = [
+        Button::Home,
+        Button::Rewind,
+        Button::Forward,
+        Button::PlayPause,
+        Button::Select,
+        Button::Left,
+        Button::Right,
+        Button::Down,
+        Button::Up,
+        Button::Back,
+        Button::InstantReplay,
+        Button::Keyboard,
+        Button::Info,
+        Button::Backspace,
+        Button::Search,
+        Button::Enter,
+        Button::Stop,
+        Button::FindRemote,
+        Button::VolumeDown,
+        Button::VolumeMute,
+        Button::VolumeUp,
+        Button::Power,
+        Button::PowerOff,
+        Button::PowerOn,
+        Button::ChannelUp,
+        Button::ChannelDown,
+        Button::InputTuner,
+        Button::InputHdmi1,
+        Button::InputHdmi2,
+        Button::InputHdmi3,
+        Button::InputHdmi4,
+        Button::InputAv1,
+        Button::Numpad1,
+        Button::Numpad2,
+        Button::Numpad3,
+        Button::Numpad4,
+        Button::Numpad5,
+        Button::Numpad6,
+        Button::Numpad7,
+        Button::Numpad8,
+        Button::Numpad9,
+        Button::Numpad0,
+        Button::NumpadDot,
+        Button::NumpadRed,
+        Button::NumpadGreen,
+        Button::NumpadYellow,
+        Button::NumpadBlue,
+        Button::NumpadExit,
+        Button::Guide,
+        Button::TextSearch,
+        Button::VoiceSearch,
+    ];
+}
+
+impl fmt::Display for Button {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
-            Button::Home => { String::from("Home") }
-            Button::Rewind => { String::from("Rev") }
-            Button::Forward => { String::from("Fwd") }
-            Button::PlayPause => { String::from("Play") }
-            Button::Select => { String::from("Select") }
-            Button::Left => { String::from("Left") }
-            Button::Right => { String::from("Right") }
-            Button::Down => { String::from("Down") }
-            Button::Up => { String::from("Up") }
-            Button::Back => { String::from("Back") }
-            Button::InstantReplay => { String::from("InstantReplay") }
-            Button::Keyboard => { String::from("Keyboard") }
-            Button::Info => { String::from("Info") }
-            Button::Backspace => { String::from("Backspace") }
-            Button::Search => { String::from("Search") }
-            Button::Enter => { String::from("Enter") }
-            Button::Stop => { String::from("Stop") }
-            Button::FindRemote => { String::from("FindRemote") }
-            Button::VolumeDown => { String::from("VolumeDown") }
-            Button::VolumeMute => { String::from("VolumeMute") }
-            Button::VolumeUp => { String::from("VolumeUp") }
-            Button::Power => { String::from("Power") }
-            Button::PowerOff => { String::from("PowerOff") }
-            Button::PowerOn => { String::from("PowerOn") }
-            Button::ChannelUp => { String::from("ChannelUp") }
-            Button::ChannelDown => { String::from("ChannelDown") }
-            Button::InputTuner => { String::from("InputTuner") }
-            Button::InputHdmi1 => { String::from("InputHDMI1") }
-            Button::InputHdmi2 => { String::from("InputHDMI2") }
-            Button::InputHdmi3 => { String::from("InputHDMI3") }
-            Button::InputHdmi4 => { String::from("InputHDMI4") }
-            Button::InputAv1 => { String::from("InputAV1") }
-            Button::Numpad1 => { String::from("1") }
-            Button::Numpad2 => { String::from("2") }
-            Button::Numpad3 => { String::from("3") }
-            Button::Numpad4 => { String::from("4") }
-            Button::Numpad5 => { String::from("5") }
-            Button::Numpad6 => { String::from("6") }
-            Button::Numpad7 => { String::from("7") }
-            Button::Numpad8 => { String::from("8") }
-            Button::Numpad9 => { String::from("9") }
-            Button::Numpad0 => { String::from("0") }
-            Button::NumpadDot => { String::from(".") }
-            Button::NumpadRed => { String::from("Red") }
-            Button::NumpadGreen => { String::from("Green") }
-            Button::NumpadYellow => { String::from("Yellow") }
-            Button::NumpadBlue => { String::from("Blue") }
-            Button::NumpadExit => { String::from("Exit") }
-            Button::Guide => { String::from("Guide") }
-            Button::TextSearch => { String::from("TextSearch") }
-            Button::VoiceSearch => { String::from("VoiceSearch") }
-            Button::Literal { character } => { format!("Lit_-{}", character) }
+            Button::Home => write!(f, "Home"),
+            Button::Rewind => write!(f, "Rev"),
+            Button::Forward => write!(f, "Fwd"),
+            Button::PlayPause => write!(f, "Play"),
+            Button::Select => write!(f, "Select"),
+            Button::Left => write!(f, "Left"),
+            Button::Right => write!(f, "Right"),
+            Button::Down => write!(f, "Down"),
+            Button::Up => write!(f, "Up"),
+            Button::Back => write!(f, "Back"),
+            Button::InstantReplay => write!(f, "InstantReplay"),
+            Button::Keyboard => write!(f, "Keyboard"),
+            Button::Info => write!(f, "Info"),
+            Button::Backspace => write!(f, "Backspace"),
+            Button::Search => write!(f, "Search"),
+            Button::Enter => write!(f, "Enter"),
+            Button::Stop => write!(f, "Stop"),
+            Button::FindRemote => write!(f, "FindRemote"),
+            Button::VolumeDown => write!(f, "VolumeDown"),
+            Button::VolumeMute => write!(f, "VolumeMute"),
+            Button::VolumeUp => write!(f, "VolumeUp"),
+            Button::Power => write!(f, "Power"),
+            Button::PowerOff => write!(f, "PowerOff"),
+            Button::PowerOn => write!(f, "PowerOn"),
+            Button::ChannelUp => write!(f, "ChannelUp"),
+            Button::ChannelDown => write!(f, "ChannelDown"),
+            Button::InputTuner => write!(f, "InputTuner"),
+            Button::InputHdmi1 => write!(f, "InputHDMI1"),
+            Button::InputHdmi2 => write!(f, "InputHDMI2"),
+            Button::InputHdmi3 => write!(f, "InputHDMI3"),
+            Button::InputHdmi4 => write!(f, "InputHDMI4"),
+            Button::InputAv1 => write!(f, "InputAV1"),
+            Button::Numpad1 => write!(f, "1"),
+            Button::Numpad2 => write!(f, "2"),
+            Button::Numpad3 => write!(f, "3"),
+            Button::Numpad4 => write!(f, "4"),
+            Button::Numpad5 => write!(f, "5"),
+            Button::Numpad6 => write!(f, "6"),
+            Button::Numpad7 => write!(f, "7"),
+            Button::Numpad8 => write!(f, "8"),
+            Button::Numpad9 => write!(f, "9"),
+            Button::Numpad0 => write!(f, "0"),
+            Button::NumpadDot => write!(f, "."),
+            Button::NumpadRed => write!(f, "Red"),
+            Button::NumpadGreen => write!(f, "Green"),
+            Button::NumpadYellow => write!(f, "Yellow"),
+            Button::NumpadBlue => write!(f, "Blue"),
+            Button::NumpadExit => write!(f, "Exit"),
+            Button::Guide => write!(f, "Guide"),
+            Button::TextSearch => write!(f, "TextSearch"),
+            Button::VoiceSearch => write!(f, "VoiceSearch"),
+            Button::Literal { character } => write!(f, "{}{}", LITERAL_PREFIX, urlencoding::encode(&character.to_string())),
         }
     }
 }
 
-impl From<&str> for Button {
-    fn from(s: &str) -> Self {
-        match s {
-            "Home" => Button::Home,
-            "Rev" => Button::Rewind,
-            "Fwd" => Button::Forward,
-            "Play" => Button::PlayPause,
-            "Select" => Button::Select,
-            "Left" => Button::Left,
-            "Right" => Button::Right,
-            "Down" => Button::Down,
-            "Up" => Button::Up,
-            "Back" => Button::Back,
-            "InstantReplay" => Button::InstantReplay,
-            "Keyboard" => Button::Keyboard,
-            "Info" => Button::Info,
-            "Backspace" => Button::Backspace,
-            "Search" => Button::Search,
-            "Enter" => Button::Enter,
-            "Stop" => Button::Stop,
-            "FindRemote" => Button::FindRemote,
-            "VolumeDown" => Button::VolumeDown,
-            "VolumeMute" => Button::VolumeMute,
-            "VolumeUp" => Button::VolumeUp,
-            "Power" => Button::Power,
-            "PowerOff" => Button::PowerOff,
-            "PowerOn" => Button::PowerOn,
-            "ChannelUp" => Button::ChannelUp,
-            "ChannelDown" => Button::ChannelDown,
-            "InputTuner" => Button::InputTuner,
-            "InputHDMI1" => Button::InputHdmi1,
-            "InputHDMI2" => Button::InputHdmi2,
-            "InputHDMI3" => Button::InputHdmi3,
-            "InputHDMI4" => Button::InputHdmi4,
-            "InputAV1" => Button::InputAv1,
-            "1" => Button::Numpad1,
-            "2" => Button::Numpad2,
-            "3" => Button::Numpad3,
-            "4" => Button::Numpad4,
-            "5" => Button::Numpad5,
-            "6" => Button::Numpad6,
-            "7" => Button::Numpad7,
-            "8" => Button::Numpad8,
-            "9" => Button::Numpad9,
-            "0" => Button::Numpad0,
-            "." => Button::NumpadDot,
-            "Red" => Button::NumpadRed,
-            "Green" => Button::NumpadGreen,
-            "Yellow" => Button::NumpadYellow,
-            "Blue" => Button::NumpadBlue,
-            "Exit" => Button::NumpadExit,
-            "Guide" => Button::Guide,
-            "TextSearch" => Button::TextSearch,
-            "VoiceSearch" => Button::VoiceSearch,
-            _ => panic!("Unrecognized button string")
+/// Error returned when a string doesn't name a known button
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseButtonError {
+    pub input: String,  // The unparseable string
+}
+
+impl fmt::Display for ParseButtonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unrecognized button string \"{}\"", self.input)
+    }
+}
+
+impl std::error::Error for ParseButtonError {}
+
+impl FromStr for Button {
+    type Err = ParseButtonError;
+
+    /// Parse an ECP key name (case-insensitive, with a few friendlier aliases) or a "Lit_" literal
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseButtonError { input: String::from(s) };
+
+        // Literals, with or without the "-" separator
+        if let Some(payload) = strip_literal_prefix(s) {
+            let decoded = urlencoding::decode(payload).map_err(|_| error())?;
+            let mut chars = decoded.chars();
+            return match (chars.next(), chars.next()) {
+                (Some(character), None) => Ok(Button::Literal { character }),
+                _ => Err(error()),
+            };
+        }
+
+        match s.trim().to_ascii_lowercase().as_str() {
+            "home" => Ok(Button::Home),
+            "rev" | "rewind" => Ok(Button::Rewind),
+            "fwd" | "forward" | "fastforward" | "ff" => Ok(Button::Forward),
+            "play" | "playpause" | "pause" => Ok(Button::PlayPause),
+            "select" | "ok" => Ok(Button::Select),
+            "left" => Ok(Button::Left),
+            "right" => Ok(Button::Right),
+            "down" => Ok(Button::Down),
+            "up" => Ok(Button::Up),
+            "back" => Ok(Button::Back),
+            "instantreplay" | "replay" => Ok(Button::InstantReplay),
+            "keyboard" => Ok(Button::Keyboard),
+            "info" => Ok(Button::Info),
+            "backspace" => Ok(Button::Backspace),
+            "search" => Ok(Button::Search),
+            "enter" => Ok(Button::Enter),
+            "stop" => Ok(Button::Stop),
+            "findremote" => Ok(Button::FindRemote),
+            "volumedown" | "voldown" => Ok(Button::VolumeDown),
+            "volumemute" | "mute" => Ok(Button::VolumeMute),
+            "volumeup" | "volup" => Ok(Button::VolumeUp),
+            "power" => Ok(Button::Power),
+            "poweroff" => Ok(Button::PowerOff),
+            "poweron" => Ok(Button::PowerOn),
+            "channelup" => Ok(Button::ChannelUp),
+            "channeldown" => Ok(Button::ChannelDown),
+            "inputtuner" | "tuner" => Ok(Button::InputTuner),
+            "inputhdmi1" | "hdmi1" => Ok(Button::InputHdmi1),
+            "inputhdmi2" | "hdmi2" => Ok(Button::InputHdmi2),
+            "inputhdmi3" | "hdmi3" => Ok(Button::InputHdmi3),
+            "inputhdmi4" | "hdmi4" => Ok(Button::InputHdmi4),
+            "inputav1" | "av1" => Ok(Button::InputAv1),
+            "1" | "numpad1" => Ok(Button::Numpad1),
+            "2" | "numpad2" => Ok(Button::Numpad2),
+            "3" | "numpad3" => Ok(Button::Numpad3),
+            "4" | "numpad4" => Ok(Button::Numpad4),
+            "5" | "numpad5" => Ok(Button::Numpad5),
+            "6" | "numpad6" => Ok(Button::Numpad6),
+            "7" | "numpad7" => Ok(Button::Numpad7),
+            "8" | "numpad8" => Ok(Button::Numpad8),
+            "9" | "numpad9" => Ok(Button::Numpad9),
+            "0" | "numpad0" => Ok(Button::Numpad0),
+            "." | "numpaddot" | "dot" => Ok(Button::NumpadDot),
+            "red" | "numpadred" => Ok(Button::NumpadRed),
+            "green" | "numpadgreen" => Ok(Button::NumpadGreen),
+            "yellow" | "numpadyellow" => Ok(Button::NumpadYellow),
+            "blue" | "numpadblue" => Ok(Button::NumpadBlue),
+            "exit" | "numpadexit" => Ok(Button::NumpadExit),
+            "guide" => Ok(Button::Guide),
+            "textsearch" => Ok(Button::TextSearch),
+            "voicesearch" => Ok(Button::VoiceSearch),
+            _ => Err(error()),
         }
     }
 }
 
-impl From<String> for Button {
-    fn from(s: String) -> Self {
-        Button::from(s.as_str())
+/// Strip the "Lit_" (or "Lit_-") prefix from a literal key name, case-insensitively
+fn strip_literal_prefix(s: &str) -> Option<&str> {
+    if s.len() < 5 || !s.is_char_boundary(4) || !s[..4].eq_ignore_ascii_case("Lit_") {
+        return None;
+    }
+    let payload = &s[4..];
+    // A bare "Lit_-" is the literal "-" itself
+    match payload.strip_prefix('-') {
+        Some(rest) if !rest.is_empty() => Some(rest),
+        _ => Some(payload),
     }
 }
 
 impl From<char> for Button {
-    fn from(c: char) -> Self {
-        Button::Literal { character: urlencoding::encode(&format!("{}", c)).chars().next().unwrap() }
+    /// Keyboard literal for a character (it's percent-encoded when sent)
+    fn from(character: char) -> Self {
+        Button::Literal { character }
     }
 }
\ No newline at end of file
@@ -0,0 +1,41 @@
+use crate::{Button, Device, Error};
+use std::time::Duration;
+
+/// Options for typing text into an on-screen keyboard
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TypingOptions {
+    pub clear:  usize,      // Backspaces to send before typing (0 leaves the field alone)
+    pub delay:  Duration,   // Pause between keystrokes, so the device doesn't drop any
+}
+
+impl Default for TypingOptions {
+    fn default() -> Self {
+        TypingOptions {
+            clear: 0,
+            delay: Duration::from_millis(100),
+        }
+    }
+}
+
+/// Type into whatever on-screen keyboard is open
+// NOTE: The keyboard needs to already be on screen (e.g. a search field), this won't open one
+impl Device {
+    /// Type text one literal at a time, using the default pacing
+    pub async fn type_text(&mut self, text: &str) -> Result<(), Error> {
+        self.type_text_with(text, TypingOptions::default()).await
+    }
+
+    /// Type text one literal at a time, optionally clearing the field first
+    pub async fn type_text_with(&mut self, text: &str, options: TypingOptions) -> Result<(), Error> {
+        let keys = std::iter::repeat_n(Button::Backspace, options.clear)
+            .chain(text.chars().map(Button::from));
+
+        for (i, key) in keys.enumerate() {
+            if i > 0 {
+                tokio::time::sleep(options.delay).await;
+            }
+            self.press_button(key).await?;
+        }
+        Ok(())
+    }
+}
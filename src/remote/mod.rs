@@ -1,39 +1,120 @@
 mod button;
+mod keyboard;
+mod script;
+mod sequence;
 
 /// Emulate use of a remote control, and help locate one
-pub use crate::remote::button::Button;
-use crate::Device;
+pub use crate::remote::button::{Button, ParseButtonError};
+pub use crate::remote::keyboard::TypingOptions;
+pub use crate::remote::script::{AppRef, Macro, MacroError, MacroStep};
+pub use crate::remote::sequence::{FailurePolicy, SequenceOptions, SequenceReport};
+use crate::{Device, Error, Transport};
+use crate::device::http_post;
 use ecp::Set;
+use std::time::Duration;
 
 /// Adds additional remote-control
 // NOTE: These require the device to be powered on
 impl Device {
     /// Press a button on the remote
     // IMPLEMENTATION NOTE: If implementing a remote UI, it's best to use Device.set_power_state(TOGGLE) instead of sending PowerOn/PowerOff button presses
-    pub async fn press_button(&mut self, button: Button) -> Result<(), String> {
+    pub async fn press_button(&mut self, button: Button) -> Result<(), Error> {
+        if self.transport == Transport::Http {
+            self.check_supported(&button).await?;
+            return self.post(&format!("keypress/{}", button)).await;
+        }
         if !self.is_connected() {
-            return Err(String::from("Not connected to device"));
+            return Err(Error::NotConnected);
         }
+        self.check_supported(&button).await?;
 
         let request = Set::PressKey { key: button.to_string() };
-        match self.send_request(request.into()).await {
-            None => Err(String::from("No response received for button press")),
-            Some(_) => Ok(())
+        self.request(request.into()).await.map(|_| ())
+    }
+
+    /// Send multiple button presses, paced with the default options and stopping at the first failure
+    pub async fn press_buttons(&mut self, buttons: Vec<Button>) -> Result<(), Error> {
+        let report = self.press_buttons_with(buttons, &SequenceOptions::default()).await;
+        match report.failed.into_iter().next() {
+            Some((_, e)) => Err(e),
+            None => Ok(()),
         }
     }
 
-    /// Send multiple button presses back-to-back
-    pub async fn press_buttons(&mut self, buttons: Vec<Button>) -> Result<(), String> {
-        // Send buttons until we reach the end, stop if one doesn't send
-        for b in buttons.into_iter() {
-            if let Err(e) = self.press_button(b).await {
-                return Err(e)
-            }
+    /// Press a button down without releasing it
+    // NOTE: Always pair this with key_up, or use hold_button which does so for you
+    // NOTE: ECP-2 has no key-down/key-up requests, so these always go over plain ECP (HTTP on port 8060),
+    //       which works without an ECP-2 connection but needs the device's HTTP control to be enabled
+    pub async fn key_down(&mut self, button: Button) -> Result<(), Error> {
+        self.check_supported(&button).await?;
+        self.post(&format!("keydown/{}", button)).await
+    }
+
+    /// Release a button previously pressed with key_down
+    pub async fn key_up(&mut self, button: Button) -> Result<(), Error> {
+        self.check_supported(&button).await?;
+        self.post(&format!("keyup/{}", button)).await
+    }
+
+    /// Hold a button down for a duration, e.g. to scroll quickly or long-press Select/Home
+    // The key-up is guaranteed: if this future is dropped before finishing, it's sent in the background
+    pub async fn hold_button(&mut self, button: Button, duration: Duration) -> Result<(), Error> {
+        // Arm the guard first so even a cancelled key-down is followed by a key-up
+        let guard = KeyUpGuard { url: Some(self.endpoint(&format!("keyup/{}", button))), timeout: self.timeout };
+        self.key_down(button).await?;
+        tokio::time::sleep(duration).await;
+        guard.release().await
+    }
+
+    /// Refuse buttons the device doesn't have, fetching its capabilities the first time
+    // A failed fetch is returned rather than skipped, so presses never go out unchecked
+    async fn check_supported(&mut self, button: &Button) -> Result<(), Error> {
+        if self.info.is_none() {
+            // Without an ECP-2 connection, device-info can still be read over HTTP
+            let info = match self.is_connected() {
+                true => self.get_device_info().await?,
+                false => self.query_device_info().await?,
+            };
+            self.info = Some(info);
+        }
+        match &self.info {
+            Some(info) if !info.supports_button(button) => Err(Error::Unsupported(button.clone())),
+            _ => Ok(()),
         }
-        Ok(())
     }
 
     // Convenience helper to send the "FindRemote" button press
-    // NOTE: None of my devices have this, so I'm not sure how to test it...
-    pub async fn find_remote(&mut self) -> Result<(), String> { self.press_button(Button::FindRemote).await }
-}
\ No newline at end of file
+    // NOTE: None of my devices have this, so press_button returns Error::Unsupported on them
+    pub async fn find_remote(&mut self) -> Result<(), Error> { self.press_button(Button::FindRemote).await }
+}
+
+/// Sends a key-up when dropped, unless it was already released
+struct KeyUpGuard {
+    url:        Option<String>, // Key-up endpoint, cleared once delivered
+    timeout:    Duration,       // Request timeout of the device it belongs to
+}
+
+impl KeyUpGuard {
+    /// Send the key-up now, and only disarm once it was delivered
+    async fn release(mut self) -> Result<(), Error> {
+        if let Some(url) = &self.url {
+            http_post(url, self.timeout).await?;
+        }
+        self.url = None;
+        Ok(())
+    }
+}
+
+impl Drop for KeyUpGuard {
+    fn drop(&mut self) {
+        if let Some(url) = self.url.take() {
+            // Can't await in drop, so hand the key-up to the runtime (if there still is one)
+            let timeout = self.timeout;
+            if let Ok(handle) = tokio::runtime::Handle::try_current() {
+                handle.spawn(async move {
+                    let _ = http_post(&url, timeout).await;
+                });
+            }
+        }
+    }
+}
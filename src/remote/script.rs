@@ -0,0 +1,220 @@
+use crate::{Button, Device, SequenceOptions};
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+// How long wait-for-app waits when no timeout is given
+const DEFAULT_APP_TIMEOUT: Duration = Duration::from_secs(10);
+// How often wait-for-app checks the active app
+const APP_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// An app referenced by a macro, either by channel id or by name
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AppRef {
+    Id(i32),
+    Name(String),
+}
+
+impl From<&str> for AppRef {
+    fn from(s: &str) -> Self {
+        match i32::from_str(s) {
+            Ok(id) => AppRef::Id(id),
+            Err(_) => AppRef::Name(String::from(s)),
+        }
+    }
+}
+
+/// A single step of a macro
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum MacroStep {
+    Press { button: Button, count: usize },
+    Wait(Duration),
+    Type(String),
+    Launch(AppRef),
+    WaitForApp { app: AppRef, timeout: Duration },
+}
+
+/// A parsed remote macro: a small line-based script of button presses, waits, text entry and app launches
+///
+/// ```text
+/// # Comments and blank lines are ignored
+/// Home
+/// wait 2s
+/// Down x3
+/// Select
+/// type "news"
+/// Enter
+/// launch Netflix
+/// wait-for-app 12 timeout 15s
+/// ```
+///
+/// Button names are anything `Button` can parse, optionally repeated with `xN`.
+/// Durations are a number followed by `ms`, `s` or `m`.
+///
+/// Each step keeps its source line number for error reporting.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Macro {
+    pub steps: Vec<(usize, MacroStep)>,
+}
+
+/// Error parsing or running a macro, pointing at the offending line
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MacroError {
+    pub line:       usize,  // 1-indexed source line
+    pub message:    String, // What went wrong
+}
+
+impl fmt::Display for MacroError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for MacroError {}
+
+impl FromStr for Macro {
+    type Err = MacroError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut steps = Vec::new();
+        for (index, raw) in s.lines().enumerate() {
+            let line = raw.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let step = parse_step(line).map_err(|message| MacroError { line: index + 1, message })?;
+            steps.push((index + 1, step));
+        }
+        Ok(Macro { steps })
+    }
+}
+
+impl Macro {
+    /// Run each step in order, stopping at the first one that fails
+    pub async fn run(&self, device: &mut Device) -> Result<(), MacroError> {
+        let pacing = SequenceOptions::default();
+        for (line, step) in self.steps.iter() {
+            let error = |message: String| MacroError { line: *line, message };
+            match step {
+                MacroStep::Press { button, count } => {
+                    let report = device.press_buttons_with(vec![button.clone(); *count], &pacing).await;
+                    if let Some((_, e)) = report.failed.into_iter().next() {
+                        return Err(error(e.to_string()));
+                    }
+                }
+                MacroStep::Wait(duration) => tokio::time::sleep(*duration).await,
+                MacroStep::Type(text) => device.type_text(text).await.map_err(|e| error(e.to_string()))?,
+                MacroStep::Launch(AppRef::Id(id)) => device.launch_app_by_id(*id).await.map_err(|e| error(e.to_string()))?,
+                MacroStep::Launch(AppRef::Name(name)) => {
+                    device.launch_app(name).await.map_err(|e| error(e.to_string()))?;
+                }
+                MacroStep::WaitForApp { app, timeout } => {
+                    let found = tokio::time::timeout(*timeout, wait_for_app(device, app)).await;
+                    if found.is_err() {
+                        return Err(error(format!("Timed out waiting for app {:?}", app)));
+                    }
+                }
+            }
+            // Give the UI a moment before the next step
+            tokio::time::sleep(pacing.delay).await;
+        }
+        Ok(())
+    }
+}
+
+impl Device {
+    /// Parse and run a remote macro (see `Macro` for the format)
+    pub async fn run_macro(&mut self, source: &str) -> Result<(), MacroError> {
+        source.parse::<Macro>()?.run(self).await
+    }
+}
+
+/// Parse one non-empty, non-comment line
+fn parse_step(line: &str) -> Result<MacroStep, String> {
+    let (keyword, rest) = match line.split_once(char::is_whitespace) {
+        Some((keyword, rest)) => (keyword, rest.trim()),
+        None => (line, ""),
+    };
+
+    match keyword.to_ascii_lowercase().as_str() {
+        "wait" => Ok(MacroStep::Wait(parse_duration(rest)?)),
+        "type" => {
+            if rest.is_empty() {
+                return Err(String::from("Nothing to type"));
+            }
+            Ok(MacroStep::Type(unquote(rest).to_string()))
+        }
+        "launch" => {
+            if rest.is_empty() {
+                return Err(String::from("Missing app to launch"));
+            }
+            Ok(MacroStep::Launch(AppRef::from(unquote(rest))))
+        }
+        "wait-for-app" => {
+            // Optional trailing "timeout <duration>"
+            let (app, timeout) = match rest.rsplit_once(" timeout ") {
+                Some((app, timeout)) => (app.trim(), parse_duration(timeout)?),
+                None => (rest, DEFAULT_APP_TIMEOUT),
+            };
+            if app.is_empty() {
+                return Err(String::from("Missing app to wait for"));
+            }
+            Ok(MacroStep::WaitForApp { app: AppRef::from(unquote(app)), timeout })
+        }
+        _ => {
+            let button = Button::from_str(keyword).map_err(|e| e.to_string())?;
+            let count = match rest {
+                "" => 1,
+                repeat => repeat.strip_prefix(['x', 'X'])
+                    .and_then(|n| usize::from_str(n.trim()).ok())
+                    .ok_or(format!("Invalid repeat \"{}\", expected e.g. x3", repeat))?,
+            };
+            Ok(MacroStep::Press { button, count })
+        }
+    }
+}
+
+/// Parse durations like "500ms", "2s", "1.5s" or "1m"
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    let s = s.trim();
+    let error = || format!("Invalid duration \"{}\", expected e.g. 500ms, 2s or 1m", s);
+    let (number, scale) = if let Some(n) = s.strip_suffix("ms") {
+        (n, 0.001)
+    } else if let Some(n) = s.strip_suffix('s') {
+        (n, 1.0)
+    } else if let Some(n) = s.strip_suffix('m') {
+        (n, 60.0)
+    } else {
+        return Err(error());
+    };
+    match f64::from_str(number.trim()) {
+        Ok(value) if value >= 0.0 && value.is_finite() => Ok(Duration::from_secs_f64(value * scale)),
+        _ => Err(error()),
+    }
+}
+
+/// Strip one pair of matching quotes, if present
+fn unquote(s: &str) -> &str {
+    for quote in ['"', '\''] {
+        if s.len() >= 2 && s.starts_with(quote) && s.ends_with(quote) {
+            return &s[1..s.len() - 1];
+        }
+    }
+    s
+}
+
+/// Poll the active app until it matches
+async fn wait_for_app(device: &Device, app: &AppRef) {
+    loop {
+        if let Ok(Some(active)) = device.get_active_app().await {
+            let matched = match app {
+                AppRef::Id(id) => active.id == *id,
+                AppRef::Name(name) => active.name.eq_ignore_ascii_case(name),
+            };
+            if matched {
+                return;
+            }
+        }
+        tokio::time::sleep(APP_POLL_INTERVAL).await;
+    }
+}
@@ -0,0 +1,90 @@
+use crate::{Button, Device, Error};
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// What to do when a key in a sequence can't be delivered
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum FailurePolicy {
+    Abort,      // Stop and leave the rest of the sequence unsent
+    Continue,   // Skip the failed key and carry on
+}
+
+/// Pacing, retries and failure handling for button sequences
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SequenceOptions {
+    pub delay:      Duration,                   // Pause after each key, so the UI doesn't drop any
+    pub delays:     HashMap<Button, Duration>,  // Per-button overrides for the pause after it (e.g. a longer one after Home)
+    pub retries:    u32,                        // Extra attempts per key before giving up on it
+    pub on_failure: FailurePolicy,              // Abort or continue once a key has exhausted its retries
+}
+
+impl Default for SequenceOptions {
+    fn default() -> Self {
+        SequenceOptions {
+            delay: Duration::from_millis(100),
+            delays: HashMap::new(),
+            retries: 0,
+            on_failure: FailurePolicy::Abort,
+        }
+    }
+}
+
+impl SequenceOptions {
+    /// Pause to take after a given button
+    fn delay_after(&self, button: &Button) -> Duration {
+        *self.delays.get(button).unwrap_or(&self.delay)
+    }
+}
+
+/// Which keys of a sequence made it to the device
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SequenceReport {
+    pub delivered:  Vec<Button>,            // Keys the device acknowledged, in order
+    pub failed:     Vec<(Button, Error)>,   // Keys that exhausted their retries, with the last error
+    pub remaining:  Vec<Button>,            // Keys never sent because the sequence was aborted
+}
+
+impl SequenceReport {
+    /// Whether every key was delivered
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty() && self.remaining.is_empty()
+    }
+}
+
+impl Device {
+    /// Send a sequence of button presses with pacing, retries and a failure policy
+    pub async fn press_buttons_with(&mut self, buttons: Vec<Button>, options: &SequenceOptions) -> SequenceReport {
+        let mut report = SequenceReport::default();
+        let mut buttons = buttons.into_iter();
+
+        while let Some(button) = buttons.next() {
+            // Try the key, retrying on failure
+            let mut result = self.press_button(button.clone()).await;
+            for _retry in 0..options.retries {
+                if result.is_ok() {
+                    break;
+                }
+                tokio::time::sleep(options.delay).await;
+                result = self.press_button(button.clone()).await;
+            }
+
+            let delay = options.delay_after(&button);
+            match result {
+                Ok(_) => report.delivered.push(button),
+                Err(e) => {
+                    report.failed.push((button, e));
+                    if options.on_failure == FailurePolicy::Abort {
+                        report.remaining = buttons.collect();
+                        break;
+                    }
+                }
+            }
+
+            // Pace the next key
+            if buttons.len() > 0 {
+                tokio::time::sleep(delay).await;
+            }
+        }
+        report
+    }
+}
@@ -0,0 +1,114 @@
+use crate::{Device, Error};
+use std::fmt;
+use std::str::FromStr;
+
+/// Content types understood by the ECP search endpoint
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SearchType {
+    Movie,
+    TvShow,
+    Person,
+    Channel,
+    Game,
+}
+
+impl fmt::Display for SearchType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SearchType::Movie => write!(f, "movie"),
+            SearchType::TvShow => write!(f, "tv-show"),
+            SearchType::Person => write!(f, "person"),
+            SearchType::Channel => write!(f, "channel"),
+            SearchType::Game => write!(f, "game"),
+        }
+    }
+}
+
+/// Error for a string that isn't a known search type
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParseSearchTypeError {
+    pub input: String,  // The unparseable string
+}
+
+impl fmt::Display for ParseSearchTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Unrecognized search type \"{}\" (expected movie, tv-show, person, channel or game)", self.input)
+    }
+}
+
+impl std::error::Error for ParseSearchTypeError {}
+
+impl FromStr for SearchType {
+    type Err = ParseSearchTypeError;
+
+    /// Parse a search type (case-insensitive, with a few aliases)
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "movie" | "film" => Ok(SearchType::Movie),
+            "tv-show" | "tvshow" | "tv" | "show" => Ok(SearchType::TvShow),
+            "person" => Ok(SearchType::Person),
+            "channel" | "app" => Ok(SearchType::Channel),
+            "game" => Ok(SearchType::Game),
+            _ => Err(ParseSearchTypeError { input: String::from(s) }),
+        }
+    }
+}
+
+/// Structured search handed to the device's search UI
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SearchQuery {
+    pub keyword:        String,             // Search term (e.g. title or name)
+    pub search_type:    Option<SearchType>, // Narrow results to one content type
+    pub season:         Option<u32>,        // Season to select for TV shows
+    pub provider_ids:   Vec<i32>,           // Channel ids to prefer, in priority order
+    pub launch:         bool,               // Launch the first matching provider automatically
+    pub match_any:      bool,               // Accept the first result, even if ambiguous
+}
+
+impl SearchQuery {
+    /// Constructor w/ only a keyword
+    pub fn new(keyword: &str) -> SearchQuery {
+        SearchQuery {
+            keyword: String::from(keyword),
+            ..Default::default()
+        }
+    }
+
+    /// Build the URL query string for the search/browse endpoint
+    pub(crate) fn to_query_string(&self) -> String {
+        let mut params: Vec<String> = vec![format!("keyword={}", urlencoding::encode(&self.keyword))];
+
+        if let Some(search_type) = &self.search_type {
+            params.push(format!("type={}", search_type));
+        }
+        if let Some(season) = self.season {
+            params.push(format!("season={}", season));
+        }
+        if !self.provider_ids.is_empty() {
+            let ids = self.provider_ids.iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+                .join(",");
+            params.push(format!("provider-id={}", ids));
+        }
+        if self.launch {
+            params.push(String::from("launch=true"));
+        }
+        if self.match_any {
+            params.push(String::from("match-any=true"));
+        }
+
+        params.join("&")
+    }
+}
+
+impl Device {
+    /// Open the device's search UI with a structured query
+    pub async fn search(&self, query: SearchQuery) -> Result<(), Error> {
+        if query.keyword.is_empty() {
+            return Err(Error::InvalidRequest(String::from("Search keyword is empty")));
+        }
+
+        self.post(&format!("search/browse?{}", query.to_query_string())).await
+    }
+}
@@ -0,0 +1,47 @@
+//! Serde support for types that don't serialize the way they're stored
+
+use crate::Button;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::time::Duration;
+
+/// Buttons are serialized by their ECP key name, e.g. "Home" or "Lit_a"
+impl Serialize for Button {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Button {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?.parse().map_err(de::Error::custom)
+    }
+}
+
+/// Default request timeout, for devices deserialized without one
+pub(crate) fn default_timeout() -> Duration {
+    crate::device::DEFAULT_TIMEOUT
+}
+
+/// MAC addresses as "aa:bb:cc:dd:ee:ff" strings
+pub(crate) mod mac {
+    use crate::device::parse_mac;
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(mac: &Option<[u8; 6]>, serializer: S) -> Result<S::Ok, S::Error> {
+        match mac {
+            Some(mac) => serializer.serialize_str(&format(mac)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<[u8; 6]>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(s) => parse_mac(&s).map(Some).ok_or_else(|| de::Error::custom(format!("invalid MAC address \"{}\"", s))),
+            None => Ok(None),
+        }
+    }
+
+    fn format(mac: &[u8; 6]) -> String {
+        mac.iter().map(|b| format!("{:02x}", b)).collect::<Vec<_>>().join(":")
+    }
+}